@@ -0,0 +1,233 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use haversine_rs::{distance, point::Point, units::Unit};
+
+use crate::db::{self, User};
+
+pub const AUDIENCE_HELP: &str =
+    "Аудитория задаётся условиями через пробел (все должны выполняться):\n\
+all – все незаблокированные пользователи\n\
+sub:advent – подписчики рассылки\n\
+since:2025-01-31 – зарегистрированы после даты\n\
+active:30 – активны за последние N дней\n\
+near:54.71,20.51,5 – сохранённая геопозиция в радиусе N км\n\
+ids:123,456 – явный список пользователей";
+
+/// Single condition a broadcast recipient has to match
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudienceFilter {
+    Subscription(String),
+    CreatedAfter(NaiveDate),
+    ActiveWithinDays(i64),
+    Near {
+        latitude: f64,
+        longitude: f64,
+        radius_km: f64,
+    },
+    Ids(Vec<i64>),
+}
+
+/// Broadcast target: non-blacklisted users matching every filter
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Audience {
+    pub filters: Vec<AudienceFilter>,
+}
+
+impl AudienceFilter {
+    fn matches(&self, user: &User, now: DateTime<Utc>) -> bool {
+        match self {
+            AudienceFilter::Subscription(subscription) => user.subscriptions.contains(subscription),
+            AudienceFilter::CreatedAfter(date) => user.created_at.date_naive() >= *date,
+            AudienceFilter::ActiveWithinDays(days) => user
+                .last_seen_at
+                .is_some_and(|seen| seen >= now - Duration::days(*days)),
+            AudienceFilter::Near {
+                latitude,
+                longitude,
+                radius_km,
+            } => match (user.home_latitude, user.home_longitude) {
+                (Some(home_latitude), Some(home_longitude)) => {
+                    let home = Point::new(home_latitude, home_longitude);
+                    let center = Point::new(*latitude, *longitude);
+                    distance(home, center, Unit::Kilometers) <= *radius_km
+                }
+                _ => false,
+            },
+            AudienceFilter::Ids(ids) => ids.contains(&user.user_id),
+        }
+    }
+}
+
+impl Audience {
+    /// Non-blacklisted users matching every filter
+    pub fn matches(&self, user: &User, now: DateTime<Utc>) -> bool {
+        !user.blacklisted && self.filters.iter().all(|f| f.matches(user, now))
+    }
+
+    /// Select the recipients among the stored users
+    pub async fn resolve(&self) -> anyhow::Result<Vec<i64>> {
+        let now = Utc::now();
+        Ok(db::get_user_records()
+            .await?
+            .into_iter()
+            .filter(|user| self.matches(user, now))
+            .map(|user| user.user_id)
+            .collect())
+    }
+}
+
+fn parse_list<T: FromStr>(value: &str) -> Option<Vec<T>> {
+    value.split(',').map(|v| v.trim().parse().ok()).collect()
+}
+
+impl FromStr for AudienceFilter {
+    type Err = String;
+
+    fn from_str(term: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Неверное условие аудитории: {}", term);
+        let (key, value) = term.split_once(':').ok_or_else(invalid)?;
+
+        match key {
+            "sub" if !value.is_empty() => Ok(AudienceFilter::Subscription(value.to_string())),
+            "since" => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(AudienceFilter::CreatedAfter)
+                .map_err(|_| invalid()),
+            "active" => match value.parse() {
+                Ok(days) if days > 0 => Ok(AudienceFilter::ActiveWithinDays(days)),
+                _ => Err(invalid()),
+            },
+            "near" => match parse_list::<f64>(value).as_deref() {
+                Some([latitude, longitude, radius_km]) if *radius_km > 0.0 => {
+                    Ok(AudienceFilter::Near {
+                        latitude: *latitude,
+                        longitude: *longitude,
+                        radius_km: *radius_km,
+                    })
+                }
+                _ => Err(invalid()),
+            },
+            "ids" => parse_list(value)
+                .filter(|ids: &Vec<i64>| !ids.is_empty())
+                .map(AudienceFilter::Ids)
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl FromStr for Audience {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let filters = expression
+            .split_whitespace()
+            .filter(|term| *term != "all")
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Audience { filters })
+    }
+}
+
+impl fmt::Display for AudienceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudienceFilter::Subscription(subscription) => write!(f, "sub:{}", subscription),
+            AudienceFilter::CreatedAfter(date) => write!(f, "since:{}", date.format("%Y-%m-%d")),
+            AudienceFilter::ActiveWithinDays(days) => write!(f, "active:{}", days),
+            AudienceFilter::Near {
+                latitude,
+                longitude,
+                radius_km,
+            } => write!(f, "near:{},{},{}", latitude, longitude, radius_km),
+            AudienceFilter::Ids(ids) => {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                write!(f, "ids:{}", ids.join(","))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Audience {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.filters.is_empty() {
+            return write!(f, "all");
+        }
+        let terms: Vec<String> = self
+            .filters
+            .iter()
+            .map(|filter| filter.to_string())
+            .collect();
+        write!(f, "{}", terms.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(user_id: i64) -> User {
+        User {
+            id: ("user", user_id.to_string().as_str()).into(),
+            user_id,
+            created_at: "2025-01-10T10:00:00Z".parse().unwrap(),
+            subscriptions: vec!["advent".to_string()],
+            updated_at: Utc::now(),
+            blacklisted: false,
+            first_name: None,
+            username: None,
+            language_code: None,
+            is_premium: false,
+            last_seen_at: None,
+            message_count: 0,
+            callback_count: 0,
+            home_latitude: Some(54.71),
+            home_longitude: Some(20.51),
+        }
+    }
+
+    #[test]
+    fn test_parse_audience() {
+        let audience: Audience = "sub:advent since:2025-01-01 near:54.7,20.5,5 ids:1,2"
+            .parse()
+            .unwrap();
+        assert_eq!(audience.filters.len(), 4);
+        assert_eq!(
+            audience.to_string(),
+            "sub:advent since:2025-01-01 near:54.7,20.5,5 ids:1,2"
+        );
+
+        assert_eq!("".parse::<Audience>().unwrap(), Audience::default());
+        assert_eq!("all".parse::<Audience>().unwrap().to_string(), "all");
+        assert!("active:0".parse::<Audience>().is_err());
+        assert!("near:54.7,20.5".parse::<Audience>().is_err());
+        assert!("city:klgd".parse::<Audience>().is_err());
+    }
+
+    #[test]
+    fn test_audience_matches() {
+        let now = Utc::now();
+        let audience: Audience = "sub:advent since:2025-01-01 near:54.7,20.5,5"
+            .parse()
+            .unwrap();
+        assert!(audience.matches(&user(1), now));
+
+        let mut blacklisted = user(1);
+        blacklisted.blacklisted = true;
+        assert!(!audience.matches(&blacklisted, now));
+
+        let far_away: Audience = "near:55.75,37.61,5".parse().unwrap();
+        assert!(!far_away.matches(&user(1), now));
+
+        let active: Audience = "active:7".parse().unwrap();
+        let mut seen = user(1);
+        assert!(!active.matches(&seen, now));
+        seen.last_seen_at = Some(now - Duration::days(2));
+        assert!(active.matches(&seen, now));
+
+        let ids: Audience = "ids:2,3".parse().unwrap();
+        assert!(!ids.matches(&user(1), now));
+        assert!(ids.matches(&user(2), now));
+    }
+}
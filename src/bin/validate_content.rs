@@ -0,0 +1,32 @@
+use std::collections::HashMap;
+use std::process::ExitCode;
+
+use ecobot::contents::{content_dir, load};
+
+/// Check the embedded content, with `CONTENT_DIR` on top when it is set, before deploying it
+fn main() -> ExitCode {
+    dotenv::dotenv().ok();
+
+    let dir = content_dir();
+    match &dir {
+        Some(dir) => println!(
+            "Validating embedded content with {} on top...",
+            dir.display()
+        ),
+        None => println!("Validating embedded content..."),
+    }
+
+    match load(dir.as_deref(), &HashMap::new()) {
+        Ok(content) => {
+            println!("{} pages are valid", content.pages.len());
+            ExitCode::SUCCESS
+        }
+        Err(problems) => {
+            for problem in &problems {
+                println!("✗ {}", problem);
+            }
+            println!("{} problems found", problems.len());
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,491 @@
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use teloxide::{
+    payloads::{EditMessageTextSetters, SendMessageSetters},
+    prelude::Requester,
+    types::{ChatId, InlineKeyboardMarkup, MessageId},
+    Bot, RequestError,
+};
+use tokio::sync::Notify;
+
+use crate::callback::{CallbackAction, ConfirmScope};
+use crate::commands::broadcast::{link_buttons, Tracking};
+use crate::commands::{
+    build_page_with_user, confirmation_keyboard, BroadcastCommand, CampaignCommand,
+};
+use crate::db::{
+    self, BroadcastJob, CampaignPost, CopiedMessage, JobStatus, RecipientStatus, UrlButton,
+};
+use crate::delivery::{classify, ErrorClass};
+use crate::local_time::format_local_datetime;
+use crate::users::blacklist_user;
+
+/// How often the worker looks for jobs when it was not woken up explicitly
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Recipients claimed and sent concurrently at once, about one second of sending at full rate
+const BATCH_SIZE: usize = 30;
+/// How often the progress message in the admin chat is refreshed
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(15);
+
+static WAKE_UP: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Wake the worker up after a new job was stored or a paused one was resumed
+pub fn notify_new_job() {
+    WAKE_UP.notify_one();
+}
+
+/// Start the background worker delivering persisted broadcast jobs
+pub fn spawn_worker(bot: Bot) {
+    tokio::spawn(async move {
+        loop {
+            match db::get_unfinished_broadcast_jobs().await {
+                Ok(jobs) => {
+                    for job in jobs {
+                        let job_id = job.id.to_string();
+                        if let Err(e) = process_job(&bot, job).await {
+                            error!("Broadcast job {} failed: {:?}", job_id, e);
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to load broadcast jobs: {:?}", e),
+            }
+
+            tokio::select! {
+                _ = WAKE_UP.notified() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+/// Recipients that were claimed but never got a final status
+fn interrupted_recipients(job: &BroadcastJob) -> Vec<i64> {
+    let claimed = &job.recipients[..job.cursor.min(job.recipients.len())];
+    claimed
+        .iter()
+        .filter(|user_id| job.statuses.get(&user_id.to_string()) == Some(&RecipientStatus::Sending))
+        .copied()
+        .collect()
+}
+
+/// Mark recipients claimed before a restart as unknown, they are never sent to again
+async fn recover_interrupted(job: &mut BroadcastJob) -> anyhow::Result<()> {
+    let interrupted: Vec<(i64, RecipientStatus)> = interrupted_recipients(job)
+        .into_iter()
+        .map(|user_id| (user_id, RecipientStatus::Unknown))
+        .collect();
+
+    if interrupted.is_empty() {
+        return Ok(());
+    }
+
+    warn!(
+        "Broadcast job {} resumed, {} recipients in unknown state",
+        job.id,
+        interrupted.len()
+    );
+    db::update_broadcast_job_progress(job, job.cursor, &interrupted).await?;
+    for (user_id, status) in interrupted {
+        job.statuses.insert(user_id.to_string(), status);
+    }
+    Ok(())
+}
+
+/// Expected time left, based on the pace of the recipients processed in this run
+pub fn estimate_eta(processed: usize, elapsed: Duration, remaining: usize) -> Option<Duration> {
+    if processed == 0 {
+        return None;
+    }
+    Some(elapsed.div_f64(processed as f64).mul_f64(remaining as f64))
+}
+
+fn format_eta(eta: Duration) -> String {
+    let minutes = eta.as_secs().div_ceil(60);
+    if minutes < 60 {
+        format!("~{} мин", minutes.max(1))
+    } else {
+        format!("~{} ч {} мин", minutes / 60, minutes % 60)
+    }
+}
+
+/// Status of a job as shown to the admin
+pub fn status_label(status: JobStatus) -> &'static str {
+    match status {
+        JobStatus::Draft => "ожидает подтверждения",
+        JobStatus::Scheduled => "запланирована",
+        JobStatus::Pending => "в очереди",
+        JobStatus::Running => "идёт отправка",
+        JobStatus::Paused => "на паузе",
+        JobStatus::Cancelled => "отменена",
+        JobStatus::Completed => "завершена",
+    }
+}
+
+/// Text of the progress message shown to the admin
+pub fn render_progress(job: &BroadcastJob, eta: Option<Duration>) -> String {
+    let state = status_label(job.status);
+    // Recipients of a scheduled job are only selected when it starts
+    if let (JobStatus::Draft | JobStatus::Scheduled, Some(scheduled_at)) =
+        (job.status, job.scheduled_at)
+    {
+        return format!(
+            "Рассылка {} ({}): {}\nВремя отправки: {}\nАудитория: «{}»",
+            job.content_label(),
+            job.id,
+            state,
+            format_local_datetime(scheduled_at),
+            job.audience
+        );
+    }
+    let blacklisted = job.count(RecipientStatus::Blacklisted);
+    let failed = job.count(RecipientStatus::Failed) + job.count(RecipientStatus::Unknown);
+
+    let mut text = format!(
+        "Рассылка {} ({}): {}\nПолучателей: {}\nУспешно: {}\nОшибок: {}\nЗаблокировано: {}\nОсталось: {}",
+        job.content_label(),
+        job.id,
+        state,
+        job.recipients.len(),
+        job.count(RecipientStatus::Sent),
+        failed + blacklisted,
+        blacklisted,
+        job.remaining()
+    );
+    if let (JobStatus::Running, Some(eta)) = (job.status, eta) {
+        text.push_str(&format!("\nОсталось времени: {}", format_eta(eta)));
+    }
+    text
+}
+
+/// Confirm, pause/resume and cancel buttons for a job that is not finished yet
+pub fn progress_keyboard(job: &BroadcastJob) -> InlineKeyboardMarkup {
+    let action = |verb: &str| CallbackAction::Confirm {
+        scope: ConfirmScope::Broadcast,
+        verb: verb.to_string(),
+        id: job.record_id(),
+    };
+    let toggle = match job.status {
+        JobStatus::Draft => return confirmation_keyboard(&action("confirm"), &action("cancel")),
+        JobStatus::Pending | JobStatus::Running => action("pause").button("⏸ Пауза"),
+        JobStatus::Paused => action("resume").button("▶️ Продолжить"),
+        JobStatus::Scheduled => {
+            return InlineKeyboardMarkup::new(vec![vec![action("cancel").button("✖️ Отменить")]])
+        }
+        JobStatus::Cancelled | JobStatus::Completed => return InlineKeyboardMarkup::default(),
+    };
+
+    InlineKeyboardMarkup::new(vec![vec![toggle, action("cancel").button("✖️ Отменить")]])
+}
+
+/// Post or refresh the progress message of a job
+pub async fn report_progress(bot: &Bot, job: &mut BroadcastJob, eta: Option<Duration>) {
+    let chat_id = ChatId(job.admin_chat_id);
+    let text = render_progress(job, eta);
+    let keyboard = progress_keyboard(job);
+
+    match job.status_message_id {
+        Some(message_id) => {
+            if let Err(e) = bot
+                .edit_message_text(chat_id, MessageId(message_id), text)
+                .reply_markup(keyboard)
+                .await
+            {
+                warn!(
+                    "Failed to update progress of broadcast job {}: {}",
+                    job.id, e
+                );
+            }
+        }
+        None => match bot.send_message(chat_id, text).reply_markup(keyboard).await {
+            Ok(message) => {
+                job.status_message_id = Some(message.id.0);
+                if let Err(e) = db::set_broadcast_job_status_message(job, message.id.0).await {
+                    error!(
+                        "Failed to store progress message of job {}: {:?}",
+                        job.id, e
+                    );
+                }
+            }
+            Err(e) => warn!("Failed to post progress of broadcast job {}: {}", job.id, e),
+        },
+    }
+}
+
+/// Send the job content to one recipient, blacklisting users that can't be reached anymore
+async fn deliver(
+    bot: &Bot,
+    user_id: i64,
+    tracking: &Tracking,
+    route: &str,
+    message: Option<&CopiedMessage>,
+    post: Option<&CampaignPost>,
+) -> RecipientStatus {
+    let sent = match (message, post) {
+        (Some(message), _) => {
+            BroadcastCommand::copy_to_user(bot, user_id, message, Some(tracking)).await
+        }
+        (None, Some(post)) => CampaignCommand::send_to_user(bot, user_id, post).await,
+        (None, None) => BroadcastCommand::send_to_user(bot, user_id, route, Some(tracking)).await,
+    };
+    let err = match sent {
+        Ok(_) => {
+            info!("Message sent to user: {}", user_id);
+            return RecipientStatus::Sent;
+        }
+        Err(err) => err,
+    };
+    error!("Failed to send message to user {}: {:?}", user_id, err);
+
+    // Blacklist user only if Telegram says they can't be reached anymore
+    let class = err.downcast_ref::<RequestError>().map(classify);
+    if class != Some(ErrorClass::Permanent) {
+        return RecipientStatus::Failed;
+    }
+    match blacklist_user(user_id).await {
+        Ok(_) => {
+            info!("User {} has been blacklisted", user_id);
+            RecipientStatus::Blacklisted
+        }
+        Err(e) => {
+            error!("Failed to blacklist user {}: {:?}", user_id, e);
+            RecipientStatus::Failed
+        }
+    }
+}
+
+/// Store the delivery totals of a stopped job in the broadcast history
+pub async fn record_totals(job: &BroadcastJob) {
+    let result = db::finish_broadcast(
+        &job.record_id(),
+        job.count(RecipientStatus::Sent),
+        job.count(RecipientStatus::Failed) + job.count(RecipientStatus::Unknown),
+        job.count(RecipientStatus::Blacklisted),
+    )
+    .await;
+    if let Err(err) = result {
+        error!("Failed to record totals of broadcast {}: {}", job.id, err);
+    }
+}
+
+/// Current status of a job as stored in the database, changed by the admin buttons
+async fn stored_status(job: &BroadcastJob) -> anyhow::Result<JobStatus> {
+    Ok(db::get_broadcast_job(&job.record_id())
+        .await?
+        .map(|stored| stored.status)
+        .unwrap_or(JobStatus::Cancelled))
+}
+
+/// URL buttons the job is about to send, a campaign day has none
+fn sent_links(job: &BroadcastJob) -> Vec<UrlButton> {
+    match (&job.message, &job.post) {
+        (Some(message), _) => message.buttons.clone(),
+        (None, Some(_)) => vec![],
+        (None, None) => build_page_with_user(&job.route, true, None)
+            .map(|(buttons, _)| link_buttons(&buttons))
+            .unwrap_or_default(),
+    }
+}
+
+async fn process_job(bot: &Bot, mut job: BroadcastJob) -> anyhow::Result<()> {
+    if job.status == JobStatus::Running {
+        info!("Resuming broadcast job {} at {}", job.id, job.cursor);
+        recover_interrupted(&mut job).await?;
+    } else {
+        info!(
+            "Starting broadcast job {} ({} recipients)",
+            job.id,
+            job.recipients.len()
+        );
+        let links = sent_links(&job);
+        if !db::claim_broadcast_job(&job, &links).await? {
+            info!(
+                "Broadcast job {} was paused or cancelled before it started",
+                job.id
+            );
+            return Ok(());
+        }
+        job.status = JobStatus::Running;
+        job.links = links;
+        if let Err(err) = db::start_broadcast(
+            Some(&job.record_id()),
+            &job.content_label(),
+            job.admin_chat_id,
+            &job.audience,
+            job.recipients.len(),
+        )
+        .await
+        {
+            error!("Failed to record broadcast {}: {}", job.id, err);
+        }
+    }
+    report_progress(bot, &mut job, None).await;
+
+    let started_at = Instant::now();
+    let started_cursor = job.cursor;
+    let mut last_report = Instant::now();
+
+    while job.cursor < job.recipients.len() {
+        match stored_status(&job).await? {
+            JobStatus::Paused => {
+                info!("Broadcast job {} paused at {}", job.id, job.cursor);
+                job.status = JobStatus::Paused;
+                report_progress(bot, &mut job, None).await;
+                return Ok(());
+            }
+            JobStatus::Cancelled => {
+                info!("Broadcast job {} cancelled at {}", job.id, job.cursor);
+                job.status = JobStatus::Cancelled;
+                record_totals(&job).await;
+                report_progress(bot, &mut job, None).await;
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let end = (job.cursor + BATCH_SIZE).min(job.recipients.len());
+        let batch = job.recipients[job.cursor..end].to_vec();
+
+        // Persist the claim before sending: a crash after this point never leads to a second send
+        job.cursor = end;
+        let claims: Vec<(i64, RecipientStatus)> = batch
+            .iter()
+            .map(|user_id| (*user_id, RecipientStatus::Sending))
+            .collect();
+        db::update_broadcast_job_progress(&job, job.cursor, &claims).await?;
+
+        // The shared limiter paces the concurrent sends
+        let tracking = Tracking {
+            broadcast_id: job.record_id(),
+            links: job.links.clone(),
+        };
+        let handles: Vec<_> = batch
+            .into_iter()
+            .map(|user_id| {
+                let bot = bot.clone();
+                let tracking = tracking.clone();
+                let route = job.route.clone();
+                let message = job.message.clone();
+                let post = job.post.clone();
+                (
+                    user_id,
+                    tokio::spawn(async move {
+                        deliver(
+                            &bot,
+                            user_id,
+                            &tracking,
+                            &route,
+                            message.as_ref(),
+                            post.as_ref(),
+                        )
+                        .await
+                    }),
+                )
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(handles.len());
+        for (user_id, handle) in handles {
+            let status = handle.await.unwrap_or_else(|e| {
+                error!("Send task for user {} failed: {:?}", user_id, e);
+                RecipientStatus::Unknown
+            });
+            results.push((user_id, status));
+        }
+
+        db::update_broadcast_job_progress(&job, job.cursor, &results).await?;
+        for (user_id, status) in results {
+            job.statuses.insert(user_id.to_string(), status);
+        }
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            let eta = estimate_eta(
+                job.cursor - started_cursor,
+                started_at.elapsed(),
+                job.remaining(),
+            );
+            report_progress(bot, &mut job, eta).await;
+            last_report = Instant::now();
+        }
+    }
+
+    db::update_broadcast_job_status(&job, JobStatus::Completed).await?;
+    job.status = JobStatus::Completed;
+    info!("Broadcast job {} completed", job.id);
+    record_totals(&job).await;
+    // A campaign day counts as delivered only once every subscriber was reached
+    if let Some(post) = &job.post {
+        if let Err(e) =
+            db::mark_campaign_day_delivered(&post.campaign, &post.season, post.day).await
+        {
+            error!(
+                "Failed to mark {} day {} delivered: {:?}",
+                post.campaign, post.day, e
+            );
+        }
+    }
+    report_progress(bot, &mut job, None).await;
+
+    let blacklisted = job.count(RecipientStatus::Blacklisted);
+    let failed = job.count(RecipientStatus::Failed) + job.count(RecipientStatus::Unknown);
+    bot.send_message(
+        ChatId(job.admin_chat_id),
+        format!(
+            "Отправка завершена.\nУспешно: {}\nОшибок: {}\nЗаблокировано: {}",
+            job.count(RecipientStatus::Sent),
+            failed + blacklisted,
+            blacklisted
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_interrupted_recipients() {
+        let statuses = HashMap::from([
+            ("1".to_string(), RecipientStatus::Sent),
+            ("2".to_string(), RecipientStatus::Sending),
+        ]);
+        let job = BroadcastJob {
+            id: ("broadcast_job", "test").into(),
+            route: "start".to_string(),
+            audience: "active".to_string(),
+            admin_chat_id: 1,
+            status: JobStatus::Running,
+            recipients: vec![1, 2, 3],
+            cursor: 2,
+            statuses,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            finished_at: None,
+            status_message_id: None,
+            scheduled_at: None,
+            message: None,
+            post: None,
+            links: vec![],
+        };
+
+        assert_eq!(interrupted_recipients(&job), vec![2]);
+        assert_eq!(job.remaining(), 1);
+        assert!(render_progress(&job, Some(Duration::from_secs(90)))
+            .contains("Осталось времени: ~2 мин"));
+    }
+
+    #[test]
+    fn test_estimate_eta() {
+        assert_eq!(estimate_eta(0, Duration::from_secs(10), 5), None);
+        assert_eq!(
+            estimate_eta(10, Duration::from_secs(20), 30),
+            Some(Duration::from_secs(60))
+        );
+    }
+}
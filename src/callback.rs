@@ -0,0 +1,337 @@
+use std::fmt;
+
+use teloxide::types::InlineKeyboardButton;
+
+/// Version tag in front of the callback data, bumped when the meaning of an action changes
+const VERSION: &str = "1";
+/// Separator of the fields of callback data, never part of routes, keys or record IDs
+const SEPARATOR: char = '|';
+/// Telegram's limit for callback data, in bytes
+pub const MAX_DATA_LEN: usize = 64;
+
+/// Admin flow a confirmation button belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmScope {
+    /// Broadcast job controls: `confirm`, `pause`, `resume`, `cancel`
+    Broadcast,
+    /// Campaign day preview: `confirm` or `resend` with `<key>_<day>`, or `cancel`
+    Campaign,
+    /// Campaign report review: `approve`, `reject`
+    Submission,
+    /// Content page edits: `publish`, `discard`, `rollback`, `reset`
+    Content,
+}
+
+impl ConfirmScope {
+    fn code(self) -> &'static str {
+        match self {
+            ConfirmScope::Broadcast => "b",
+            ConfirmScope::Campaign => "c",
+            ConfirmScope::Submission => "s",
+            ConfirmScope::Content => "e",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "b" => Some(ConfirmScope::Broadcast),
+            "c" => Some(ConfirmScope::Campaign),
+            "s" => Some(ConfirmScope::Submission),
+            "e" => Some(ConfirmScope::Content),
+            _ => None,
+        }
+    }
+}
+
+/// What an inline button does, carried in its callback data as `<version>|<tag>|<fields>`
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallbackAction {
+    /// Open a content page
+    Navigate(String),
+    /// Open another page of a menu with many children
+    Paginate {
+        route: String,
+        page: usize,
+    },
+    Subscribe(String),
+    Unsubscribe(String),
+    /// Start a report on a published campaign day
+    Report {
+        campaign: String,
+        day: u32,
+    },
+    /// Stop waiting for the report the user started
+    CancelReport,
+    /// Resend a past campaign day
+    CatchUp {
+        campaign: String,
+        day: u32,
+    },
+    /// Admin decision in one of the confirmation flows, on the record `id`
+    Confirm {
+        scope: ConfirmScope,
+        verb: String,
+        id: String,
+    },
+    /// Button of a broadcast message, counted before the action it wraps is handled
+    Tracked {
+        broadcast: String,
+        action: Box<CallbackAction>,
+    },
+    /// URL button of a broadcast message, counted before the link is sent to the user.
+    /// `button` is the position of the link among the URL buttons of the message
+    Link {
+        broadcast: String,
+        button: usize,
+    },
+}
+
+impl CallbackAction {
+    /// Callback data of the action
+    pub fn encode(&self) -> String {
+        format!("{}{}{}", VERSION, SEPARATOR, self.encode_body())
+    }
+
+    fn encode_body(&self) -> String {
+        let fields: Vec<String> = match self {
+            CallbackAction::Navigate(route) => vec!["n".into(), route.clone()],
+            CallbackAction::Paginate { route, page } => {
+                vec!["p".into(), route.clone(), page.to_string()]
+            }
+            CallbackAction::Subscribe(kind) => vec!["s".into(), kind.clone()],
+            CallbackAction::Unsubscribe(kind) => vec!["u".into(), kind.clone()],
+            CallbackAction::Report { campaign, day } => {
+                vec!["r".into(), campaign.clone(), day.to_string()]
+            }
+            CallbackAction::CatchUp { campaign, day } => {
+                vec!["d".into(), campaign.clone(), day.to_string()]
+            }
+            CallbackAction::CancelReport => vec!["q".into(), String::new()],
+            CallbackAction::Confirm { scope, verb, id } => {
+                vec!["c".into(), scope.code().into(), verb.clone(), id.clone()]
+            }
+            CallbackAction::Tracked { broadcast, action } => {
+                vec!["t".into(), broadcast.clone(), action.encode_body()]
+            }
+            CallbackAction::Link { broadcast, button } => {
+                vec!["l".into(), broadcast.clone(), button.to_string()]
+            }
+        };
+        fields.join(&SEPARATOR.to_string())
+    }
+
+    /// Whether the encoded action is within Telegram's limit for callback data
+    pub fn fits(&self) -> bool {
+        self.encode().len() <= MAX_DATA_LEN
+    }
+
+    /// Inline button running the action
+    pub fn button(&self, label: impl Into<String>) -> InlineKeyboardButton {
+        InlineKeyboardButton::callback(label, self.encode())
+    }
+
+    /// Action of callback data. `None` for data of an unknown version, or of old admin
+    /// buttons, which the user is told have expired
+    pub fn decode(data: &str) -> Option<Self> {
+        match data.split_once(SEPARATOR) {
+            Some((VERSION, body)) => Self::decode_body(body),
+            Some(_) => None,
+            None => Self::decode_legacy(data),
+        }
+    }
+
+    fn decode_body(body: &str) -> Option<Self> {
+        let (tag, fields) = body.split_once(SEPARATOR)?;
+        let action = match tag {
+            "n" => CallbackAction::Navigate(fields.to_string()),
+            "p" => {
+                let (route, page) = fields.split_once(SEPARATOR)?;
+                CallbackAction::Paginate {
+                    route: route.to_string(),
+                    page: page.parse().ok()?,
+                }
+            }
+            "s" => CallbackAction::Subscribe(fields.to_string()),
+            "u" => CallbackAction::Unsubscribe(fields.to_string()),
+            "q" => CallbackAction::CancelReport,
+            "r" | "d" => {
+                let (campaign, day) = fields.split_once(SEPARATOR)?;
+                let campaign = campaign.to_string();
+                let day = day.parse().ok()?;
+                match tag {
+                    "r" => CallbackAction::Report { campaign, day },
+                    _ => CallbackAction::CatchUp { campaign, day },
+                }
+            }
+            "c" => {
+                let mut fields = fields.splitn(3, SEPARATOR);
+                CallbackAction::Confirm {
+                    scope: ConfirmScope::from_code(fields.next()?)?,
+                    verb: fields.next()?.to_string(),
+                    id: fields.next().unwrap_or_default().to_string(),
+                }
+            }
+            "t" => {
+                let (broadcast, action) = fields.split_once(SEPARATOR)?;
+                CallbackAction::Tracked {
+                    broadcast: broadcast.to_string(),
+                    action: Box::new(Self::decode_body(action)?),
+                }
+            }
+            "l" => {
+                let (broadcast, button) = fields.split_once(SEPARATOR)?;
+                CallbackAction::Link {
+                    broadcast: broadcast.to_string(),
+                    button: button.parse().ok()?,
+                }
+            }
+            _ => return None,
+        };
+        Some(action)
+    }
+
+    /// Buttons sent before the data was versioned, still in users' chat history
+    fn decode_legacy(data: &str) -> Option<Self> {
+        let campaign_day = |data: &str| {
+            let (campaign, day) = data.rsplit_once('_')?;
+            Some((campaign.to_string(), day.parse().ok()?))
+        };
+
+        if let Some(tracked) = data.strip_prefix("/t_") {
+            let (broadcast, action) = tracked.split_once('_')?;
+            return Some(CallbackAction::Tracked {
+                broadcast: broadcast.to_string(),
+                action: Box::new(Self::decode_legacy(action)?),
+            });
+        }
+        if let Some(kind) = data.strip_prefix("/subscribe_") {
+            return Some(CallbackAction::Subscribe(kind.to_string()));
+        }
+        if let Some(kind) = data.strip_prefix("/unsubscribe_") {
+            return Some(CallbackAction::Unsubscribe(kind.to_string()));
+        }
+        if let Some(day) = data.strip_prefix("/campaignreport_") {
+            let (campaign, day) = campaign_day(day)?;
+            return Some(CallbackAction::Report { campaign, day });
+        }
+        if let Some(day) = data.strip_prefix("/campaignday_") {
+            let (campaign, day) = campaign_day(day)?;
+            return Some(CallbackAction::CatchUp { campaign, day });
+        }
+        // Old admin buttons are short-lived anyway, the current ones are a command away
+        let admin_prefixes = ["/broadcast_", "/campaign_", "/submission_", "/contentedit_"];
+        if admin_prefixes.iter().any(|prefix| data.starts_with(prefix)) {
+            return None;
+        }
+
+        let route = data.trim_start_matches('/');
+        (!route.is_empty()).then(|| CallbackAction::Navigate(route.to_string()))
+    }
+}
+
+/// Readable form in the style of the commands, for logs and broadcast click counts
+impl fmt::Display for CallbackAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackAction::Navigate(route) => write!(f, "/{}", route),
+            CallbackAction::Paginate { route, page } => write!(f, "/{} ({})", route, page + 1),
+            CallbackAction::Subscribe(kind) => write!(f, "/subscribe_{}", kind),
+            CallbackAction::Unsubscribe(kind) => write!(f, "/unsubscribe_{}", kind),
+            CallbackAction::Report { campaign, day } => {
+                write!(f, "/campaignreport_{}_{}", campaign, day)
+            }
+            CallbackAction::CatchUp { campaign, day } => {
+                write!(f, "/campaignday_{}_{}", campaign, day)
+            }
+            CallbackAction::CancelReport => write!(f, "cancel report"),
+            CallbackAction::Confirm { scope, verb, id } => {
+                write!(f, "{:?} {} {}", scope, verb, id)
+            }
+            CallbackAction::Tracked { broadcast, action } => {
+                write!(f, "{} (broadcast {})", action, broadcast)
+            }
+            CallbackAction::Link { broadcast, button } => {
+                write!(f, "link {} (broadcast {})", button + 1, broadcast)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let actions = [
+            CallbackAction::Navigate("plastic".into()),
+            CallbackAction::Paginate {
+                route: "recycling".into(),
+                page: 2,
+            },
+            CallbackAction::Subscribe("advent".into()),
+            CallbackAction::Report {
+                campaign: "advent".into(),
+                day: 17,
+            },
+            CallbackAction::CancelReport,
+            CallbackAction::Confirm {
+                scope: ConfirmScope::Campaign,
+                verb: "cancel".into(),
+                id: String::new(),
+            },
+            CallbackAction::Tracked {
+                broadcast: "abc123".into(),
+                action: Box::new(CallbackAction::CatchUp {
+                    campaign: "advent".into(),
+                    day: 3,
+                }),
+            },
+            CallbackAction::Link {
+                broadcast: "abc123".into(),
+                button: 1,
+            },
+        ];
+        for action in actions {
+            assert!(action.fits());
+            assert_eq!(CallbackAction::decode(&action.encode()), Some(action));
+        }
+        assert_eq!(
+            CallbackAction::Navigate("plastic".into()).encode(),
+            "1|n|plastic"
+        );
+        assert_eq!(CallbackAction::decode("2|n|plastic"), None);
+        assert_eq!(CallbackAction::decode("1|x|plastic"), None);
+    }
+
+    #[test]
+    fn test_decode_legacy() {
+        assert_eq!(
+            CallbackAction::decode("/plastic"),
+            Some(CallbackAction::Navigate("plastic".into()))
+        );
+        assert_eq!(
+            CallbackAction::decode("start"),
+            Some(CallbackAction::Navigate("start".into()))
+        );
+        assert_eq!(
+            CallbackAction::decode("/t_abc123_/subscribe_main"),
+            Some(CallbackAction::Tracked {
+                broadcast: "abc123".into(),
+                action: Box::new(CallbackAction::Subscribe("main".into())),
+            })
+        );
+        assert_eq!(
+            CallbackAction::decode("/campaignreport_advent_17"),
+            Some(CallbackAction::Report {
+                campaign: "advent".into(),
+                day: 17,
+            })
+        );
+        assert_eq!(CallbackAction::decode("/broadcast_pause_abc123"), None);
+        assert_eq!(
+            CallbackAction::Subscribe("main".into()).to_string(),
+            "/subscribe_main"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::local_time::parse_local_datetime;
+
+/// How long after its publish time a day is still delivered automatically;
+/// older days are only available to catch up on
+pub const DELIVERY_WINDOW: Duration = Duration::hours(24);
+
+pub static CAMPAIGNS: Lazy<Vec<Campaign>> = Lazy::new(|| {
+    Campaign::parse_all(include_str!("./campaigns.json")).expect("Invalid campaigns.json")
+});
+
+/// Single scheduled day of a campaign
+#[derive(Debug, Clone, PartialEq)]
+pub struct CampaignDay {
+    pub day: u32,
+    /// Content file with the HTML text
+    pub content: String,
+    /// File in `src/assets` (or an external URL) sent with the text as its caption
+    pub image: Option<String>,
+    pub publish_at: DateTime<Utc>,
+}
+
+/// Subscription-based series of tasks, like the advent calendar: days ordered by publish time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Campaign {
+    /// Subscription key, also used in commands and callback data
+    pub key: String,
+    pub title: String,
+    /// Distinguishes repeated runs of the same campaign in the stored deliveries and reports
+    pub season: String,
+    /// Content file sent when a subscriber opts out with "стоп"
+    pub opt_out: String,
+    pub winner_message: String,
+    pub days: Vec<CampaignDay>,
+}
+
+#[derive(Deserialize)]
+struct CampaignFile {
+    key: String,
+    title: String,
+    season: String,
+    opt_out: String,
+    winner_message: String,
+    days: Vec<DayFile>,
+}
+
+#[derive(Deserialize)]
+struct DayFile {
+    day: u32,
+    content: String,
+    #[serde(default)]
+    image: Option<String>,
+    /// Kaliningrad date and time, like `2025-12-01 10:00`
+    publish_at: String,
+}
+
+/// Campaign with the given subscription key
+pub fn campaign(key: &str) -> Option<&'static Campaign> {
+    CAMPAIGNS.iter().find(|campaign| campaign.key == key)
+}
+
+impl Campaign {
+    pub fn parse_all(json: &str) -> Result<Vec<Self>, String> {
+        let files: Vec<CampaignFile> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        files.into_iter().map(Campaign::from_file).collect()
+    }
+
+    fn from_file(file: CampaignFile) -> Result<Self, String> {
+        let mut days = file
+            .days
+            .into_iter()
+            .map(|day| {
+                let publish_at = parse_local_datetime(&day.publish_at)
+                    .ok_or_else(|| format!("Invalid publish_at of {} day {}", file.key, day.day))?;
+                Ok(CampaignDay {
+                    day: day.day,
+                    content: day.content,
+                    image: day.image,
+                    publish_at,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        days.sort_by_key(|day| day.publish_at);
+
+        Ok(Campaign {
+            key: file.key,
+            title: file.title,
+            season: file.season,
+            opt_out: file.opt_out,
+            winner_message: file.winner_message,
+            days,
+        })
+    }
+
+    pub fn day(&self, day: u32) -> Option<&CampaignDay> {
+        self.days.iter().find(|d| d.day == day)
+    }
+
+    /// Days already published at `now`, oldest first
+    pub fn published(&self, now: DateTime<Utc>) -> impl Iterator<Item = &CampaignDay> {
+        self.days.iter().filter(move |day| day.publish_at <= now)
+    }
+
+    /// Day published at `now` by its number
+    pub fn published_day(&self, day: u32, now: DateTime<Utc>) -> Option<&CampaignDay> {
+        self.day(day).filter(|day| day.publish_at <= now)
+    }
+
+    /// Latest published day, or the first one before the campaign starts
+    pub fn current(&self, now: DateTime<Utc>) -> Option<&CampaignDay> {
+        self.published(now).last().or(self.days.first())
+    }
+
+    /// Published days still within the delivery window that were not delivered yet
+    pub fn due<'a>(
+        &'a self,
+        now: DateTime<Utc>,
+        delivered: &'a [u32],
+    ) -> impl Iterator<Item = &'a CampaignDay> {
+        self.published(now)
+            .filter(move |day| now < day.publish_at + DELIVERY_WINDOW)
+            .filter(move |day| !delivered.contains(&day.day))
+    }
+}
+
+/// Split `<campaign key>_<day>` callback data
+pub fn parse_campaign_day(data: &str) -> Option<(&'static Campaign, u32)> {
+    let (key, day) = data.rsplit_once('_')?;
+    Some((campaign(key)?, day.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMPAIGNS_JSON: &str = r#"[{
+        "key": "plastic_free",
+        "title": "Июль без пластика",
+        "season": "test",
+        "opt_out": "unsubscribe_advent.md",
+        "winner_message": "Поздравляем!",
+        "days": [
+            {"day": 2, "content": "advent.md", "image": "https://example.com/2.jpg", "publish_at": "2025-12-02 10:00"},
+            {"day": 1, "content": "advent.md", "publish_at": "2025-12-01 10:00"}
+        ]
+    }]"#;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        parse_local_datetime(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_campaigns() {
+        let campaigns = Campaign::parse_all(CAMPAIGNS_JSON).unwrap();
+        let campaign = &campaigns[0];
+        assert_eq!(campaign.days[0].day, 1);
+        assert_eq!(campaign.days[0].image, None);
+        assert_eq!(
+            campaign.day(2).unwrap().image.as_deref(),
+            Some("https://example.com/2.jpg")
+        );
+
+        assert!(
+            Campaign::parse_all(&CAMPAIGNS_JSON.replace("2025-12-01 10:00", "1 декабря")).is_err()
+        );
+        assert!(!CAMPAIGNS.is_empty());
+    }
+
+    #[test]
+    fn test_due_days() {
+        let campaign = &Campaign::parse_all(CAMPAIGNS_JSON).unwrap()[0];
+
+        let before = at("2025-11-30 10:00");
+        assert_eq!(campaign.due(before, &[]).count(), 0);
+        assert_eq!(campaign.current(before).unwrap().day, 1);
+        assert_eq!(campaign.published_day(1, before), None);
+
+        let day_two: Vec<u32> = campaign
+            .due(at("2025-12-02 11:00"), &[])
+            .map(|day| day.day)
+            .collect();
+        // Day 1 is past its delivery window and left for catching up
+        assert_eq!(day_two, vec![2]);
+        assert_eq!(campaign.due(at("2025-12-02 11:00"), &[2]).count(), 0);
+        assert_eq!(campaign.published(at("2025-12-02 11:00")).count(), 2);
+        assert_eq!(campaign.current(at("2025-12-02 11:00")).unwrap().day, 2);
+    }
+
+    #[test]
+    fn test_parse_campaign_day() {
+        let (campaign, day) = parse_campaign_day("advent_17").unwrap();
+        assert_eq!((campaign.key.as_str(), day), ("advent", 17));
+        assert!(parse_campaign_day("marathon_1").is_none());
+        assert!(parse_campaign_day("advent").is_none());
+    }
+}
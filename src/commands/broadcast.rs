@@ -1,30 +1,223 @@
 use log::{error, info};
+use reqwest::Url;
 use teloxide::{
-    payloads::SendMessageSetters,
+    payloads::{CopyMessageSetters, SendMessageSetters},
     prelude::Requester,
-    types::{ChatId, ParseMode},
+    requests::Request,
+    types::{
+        ChatId, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, MessageId,
+    },
     Bot,
 };
 
-use crate::users::{self, blacklist_user, get_active_users};
+use crate::audience::{Audience, AUDIENCE_HELP};
+use crate::broadcaster;
+use crate::callback::CallbackAction;
+use crate::db::{self, CopiedMessage, JobStatus, UrlButton};
+use crate::delivery::send_with_retry;
+use crate::local_time::format_local_datetime;
 
-use super::common::build_details_with_user;
+use super::common::build_page_with_user;
+
+/// Number of broadcasts listed by /broadcasts
+const HISTORY_LIMIT: usize = 10;
+
+/// Buttons of the progress message of a job
+const CONTROL_VERBS: &[&str] = &["confirm", "pause", "resume", "cancel"];
+
+/// Status a job moves to when the admin presses one of its buttons, `None` when the button
+/// does not apply to the current status: only a draft is confirmed, becoming scheduled when it
+/// has a start time, only a queued or running job is paused, only a paused one is resumed, and
+/// a finished one stays as it is. A job paused before the worker `started` it is queued again,
+/// so the worker starts it from the beginning, history entry included
+fn transition(current: JobStatus, scheduled: bool, started: bool, verb: &str) -> Option<JobStatus> {
+    match (verb, current) {
+        ("confirm", JobStatus::Draft) if scheduled => Some(JobStatus::Scheduled),
+        ("confirm", JobStatus::Draft) => Some(JobStatus::Pending),
+        ("pause", JobStatus::Pending | JobStatus::Running) => Some(JobStatus::Paused),
+        ("resume", JobStatus::Paused) if started => Some(JobStatus::Running),
+        ("resume", JobStatus::Paused) => Some(JobStatus::Pending),
+        (
+            "cancel",
+            JobStatus::Draft
+            | JobStatus::Scheduled
+            | JobStatus::Pending
+            | JobStatus::Running
+            | JobStatus::Paused,
+        ) => Some(JobStatus::Cancelled),
+        _ => None,
+    }
+}
 
 pub struct BroadcastCommand;
 
+/// What a broadcast message needs to count button presses: the broadcast and the links it
+/// was started with
+#[derive(Debug, Clone)]
+pub struct Tracking {
+    pub broadcast_id: String,
+    pub links: Vec<UrlButton>,
+}
+
+/// Route the buttons of a broadcast message through its click counter. URL buttons among the
+/// links of the broadcast become callbacks that are counted and then answered with the link
+pub fn track_buttons(markup: InlineKeyboardMarkup, tracking: &Tracking) -> InlineKeyboardMarkup {
+    let rows = markup
+        .inline_keyboard
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|mut button| {
+                    let tracked = match &button.kind {
+                        InlineKeyboardButtonKind::CallbackData(data) => {
+                            CallbackAction::decode(data).map(|action| CallbackAction::Tracked {
+                                broadcast: tracking.broadcast_id.clone(),
+                                action: Box::new(action),
+                            })
+                        }
+                        // A link the content gained after the start stays a plain URL button
+                        InlineKeyboardButtonKind::Url(url) => tracking
+                            .links
+                            .iter()
+                            .position(|link| {
+                                link.text == button.text
+                                    && Url::parse(&link.url).as_ref() == Ok(url)
+                            })
+                            .map(|position| CallbackAction::Link {
+                                broadcast: tracking.broadcast_id.clone(),
+                                button: position,
+                            }),
+                        _ => None,
+                    };
+                    // Buttons that would exceed the limit keep working, just untracked
+                    if let Some(tracked) = tracked.filter(CallbackAction::fits) {
+                        button.kind = InlineKeyboardButtonKind::CallbackData(tracked.encode());
+                    }
+                    button
+                })
+                .collect()
+        })
+        .collect::<Vec<Vec<_>>>();
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// URL buttons of a keyboard, in order
+pub fn link_buttons(markup: &InlineKeyboardMarkup) -> Vec<UrlButton> {
+    markup
+        .inline_keyboard
+        .iter()
+        .flatten()
+        .filter_map(|button| match &button.kind {
+            InlineKeyboardButtonKind::Url(url) => Some(UrlButton {
+                text: button.text.clone(),
+                url: url.to_string(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Keyboard with one URL button per row, `None` when there are no buttons
+pub fn url_buttons(
+    buttons: &[UrlButton],
+) -> Result<Option<InlineKeyboardMarkup>, Box<dyn std::error::Error + Send + Sync>> {
+    if buttons.is_empty() {
+        return Ok(None);
+    }
+    let rows = buttons
+        .iter()
+        .map(|button| {
+            let url = Url::parse(&button.url)
+                .map_err(|e| format!("Invalid button URL {}: {}", button.url, e))?;
+            Ok(vec![InlineKeyboardButton::url(&button.text, url)])
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Some(InlineKeyboardMarkup::new(rows)))
+}
+
 impl BroadcastCommand {
-    /// Send message to a single user
-    async fn send_to_user(
+    /// Send message to a single user, counting button presses when `tracking` is given
+    pub async fn send_to_user(
         bot: &Bot,
         user_id: i64,
         route: &str,
+        tracking: Option<&Tracking>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (mut buttons, page) = build_page_with_user(route, true, Some(user_id))?;
+        if let Some(tracking) = tracking {
+            buttons = track_buttons(buttons, tracking);
+        }
+
+        send_with_retry(user_id, || {
+            let mut request = bot
+                .send_message(ChatId(user_id), &page.body)
+                .disable_web_page_preview(true)
+                .reply_markup(buttons.clone());
+            if let Some(parse_mode) = page.parse_mode.parse_mode() {
+                request = request.parse_mode(parse_mode);
+            }
+            request.send()
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Copy an admin-composed message to a single user, counting button presses when
+    /// `tracking` is given
+    pub async fn copy_to_user(
+        bot: &Bot,
+        user_id: i64,
+        message: &CopiedMessage,
+        tracking: Option<&Tracking>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let (buttons, content) = build_details_with_user(route, true, Some(user_id))?;
+        let mut buttons = url_buttons(&message.buttons)?;
+        if let Some(tracking) = tracking {
+            buttons = buttons.map(|buttons| track_buttons(buttons, tracking));
+        }
+
+        send_with_retry(user_id, || {
+            let request = bot.copy_message(
+                ChatId(user_id),
+                ChatId(message.chat_id),
+                MessageId(message.message_id),
+            );
+            match &buttons {
+                Some(buttons) => request.reply_markup(buttons.clone()).send(),
+                None => request.send(),
+            }
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Count a press on a tracked URL button and send the user its link
+    pub async fn open_link(
+        bot: &Bot,
+        user_id: i64,
+        broadcast_id: &str,
+        button: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Looked up among the links the broadcast was started with, whatever the content is now
+        let link = db::get_broadcast_job(broadcast_id)
+            .await?
+            .and_then(|mut job| (button < job.links.len()).then(|| job.links.swap_remove(button)))
+            .and_then(|link| Some((link.text, Url::parse(&link.url).ok()?)));
+        let Some((text, url)) = link else {
+            bot.send_message(ChatId(user_id), "Эта ссылка больше недоступна.")
+                .await?;
+            return Ok(());
+        };
 
-        bot.send_message(ChatId(user_id), &content)
+        if let Err(e) = db::record_broadcast_click(broadcast_id, url.as_str(), user_id).await {
+            error!("Failed to record broadcast click: {:?}", e);
+        }
+        bot.send_message(ChatId(user_id), format!("Ссылка: {}", url))
             .disable_web_page_preview(true)
-            .parse_mode(ParseMode::Html)
-            .reply_markup(buttons)
+            .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                InlineKeyboardButton::url(text, url),
+            ]]))
             .await?;
 
         Ok(())
@@ -37,7 +230,7 @@ impl BroadcastCommand {
         test_user_id: i64,
         route: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        match Self::send_to_user(bot, test_user_id, route).await {
+        match Self::send_to_user(bot, test_user_id, route, None).await {
             Ok(_) => {
                 info!("Test message sent to user: {}", test_user_id);
             }
@@ -57,51 +250,267 @@ impl BroadcastCommand {
         Ok(())
     }
 
-    /// Broadcast message to all users (admin command)
-    pub async fn send_to_all(
+    /// Parse an audience expression, explaining the syntax to the admin when it is invalid
+    async fn parse_audience(
         bot: &Bot,
         admin_chat_id: ChatId,
-        route: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let users = get_active_users().await?;
-        info!("Broadcasting to {} active users", users.len());
-
-        let mut success_count = 0;
-        let mut error_count = 0;
-        let mut blacklisted_count = 0;
-
-        for user_id in users {
-            match Self::send_to_user(bot, user_id, route).await {
-                Ok(_) => {
-                    success_count += 1;
-                    info!("Message sent to user: {}", user_id);
-                }
-                Err(err) => {
-                    error_count += 1;
-                    error!("Failed to send message to user {}: {:?}", user_id, err);
-
-                    // Blacklist user on send failure
-                    if let Err(e) = blacklist_user(user_id).await {
-                        error!("Failed to blacklist user {}: {:?}", user_id, e);
-                    } else {
-                        blacklisted_count += 1;
-                        info!("User {} has been blacklisted", user_id);
-                    }
-                }
+        expression: &str,
+    ) -> Result<Option<Audience>, Box<dyn std::error::Error + Send + Sync>> {
+        match expression.parse::<Audience>() {
+            Ok(audience) => Ok(Some(audience)),
+            Err(err) => {
+                bot.send_message(admin_chat_id, format!("{}\n\n{}", err, AUDIENCE_HELP))
+                    .await?;
+                Ok(None)
             }
-
-            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
         }
+    }
+
+    /// Show how many users an audience expression selects (admin command)
+    pub async fn preview_audience(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        expression: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(audience) = Self::parse_audience(bot, admin_chat_id, expression).await? else {
+            return Ok(());
+        };
+        let users = audience.resolve().await?;
 
         bot.send_message(
             admin_chat_id,
-            format!(
-                "Отправка завершена.\nУспешно: {}\nОшибок: {}\nЗаблокировано: {}",
-                success_count, error_count, blacklisted_count
-            ),
+            format!("Аудитория «{}»: {} получателей.", audience, users.len()),
         )
         .await?;
 
         Ok(())
     }
+
+    /// Preview a broadcast to the users matching an audience expression and ask for confirmation (admin command)
+    pub async fn send_to_all(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        route: &str,
+        audience: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(audience) = Self::parse_audience(bot, admin_chat_id, audience).await? else {
+            return Ok(());
+        };
+        // Render the route to the admin first: a typo fails here, not for every recipient
+        if let Err(err) = Self::send_to_user(bot, admin_chat_id.0, route, None).await {
+            bot.send_message(
+                admin_chat_id,
+                format!("Ошибка маршрута {}: {:?}", route, err),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let users = audience.resolve().await?;
+        info!(
+            "Broadcast of {} to {} users of audience {} awaits confirmation",
+            route,
+            users.len(),
+            audience
+        );
+
+        let mut job =
+            db::create_broadcast_job(route, None, &audience.to_string(), admin_chat_id.0, users)
+                .await?;
+        broadcaster::report_progress(bot, &mut job, None).await;
+
+        Ok(())
+    }
+
+    /// Confirm, pause, resume or cancel a broadcast job from its progress message buttons (admin only)
+    pub async fn control(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        verb: &str,
+        record_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if !CONTROL_VERBS.contains(&verb) {
+            return Err(format!("Unknown broadcast action: {}", verb).into());
+        }
+
+        let Some(mut job) = db::get_broadcast_job(record_id).await? else {
+            bot.send_message(admin_chat_id, "Рассылка не найдена")
+                .await?;
+            return Ok(());
+        };
+        // The worker records the history entry when it starts the job
+        let started = db::get_broadcast(record_id).await?.is_some();
+        // Buttons of an older progress message may not match the job anymore
+        let Some(status) = transition(job.status, job.scheduled_at.is_some(), started, verb) else {
+            bot.send_message(
+                admin_chat_id,
+                format!(
+                    "Кнопка уже не действует: рассылка {}",
+                    broadcaster::status_label(job.status)
+                ),
+            )
+            .await?;
+            return Ok(());
+        };
+
+        info!("Broadcast job {} set to {:?} by admin", job.id, status);
+        db::update_broadcast_job_status(&job, status).await?;
+        // No worker is left to close the history entry of a paused job
+        if started && job.status == JobStatus::Paused && status == JobStatus::Cancelled {
+            broadcaster::record_totals(&job).await;
+        }
+        job.status = status;
+        broadcaster::report_progress(bot, &mut job, None).await;
+        if matches!(status, JobStatus::Pending | JobStatus::Running) {
+            broadcaster::notify_new_job();
+        }
+
+        Ok(())
+    }
+
+    /// List recent broadcasts with delivery totals and button presses (admin command)
+    pub async fn history(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let broadcasts = db::get_recent_broadcasts(HISTORY_LIMIT).await?;
+        if broadcasts.is_empty() {
+            bot.send_message(admin_chat_id, "Рассылок ещё не было.")
+                .await?;
+            return Ok(());
+        }
+
+        let mut lines = vec![];
+        for broadcast in broadcasts {
+            let clicks = db::get_broadcast_clicks(&broadcast.record_id()).await?;
+            let clicks: Vec<String> = clicks
+                .iter()
+                .map(|click| format!("{} – {}", click.data, click.count))
+                .collect();
+
+            lines.push(format!(
+                "{} {} («{}»)\nПолучателей: {}, успешно: {}, ошибок: {}, заблокировано: {}{}\nНажатия: {}",
+                format_local_datetime(broadcast.started_at),
+                broadcast.content,
+                broadcast.audience,
+                broadcast.recipients,
+                broadcast.sent,
+                broadcast.failed,
+                broadcast.blacklisted,
+                if broadcast.finished_at.is_none() { " (идёт)" } else { "" },
+                if clicks.is_empty() { "нет".to_string() } else { clicks.join(", ") }
+            ));
+        }
+
+        bot.send_message(admin_chat_id, lines.join("\n\n")).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transition() {
+        assert_eq!(
+            transition(JobStatus::Draft, false, true, "confirm"),
+            Some(JobStatus::Pending)
+        );
+        assert_eq!(
+            transition(JobStatus::Running, false, true, "pause"),
+            Some(JobStatus::Paused)
+        );
+        assert_eq!(
+            transition(JobStatus::Paused, false, true, "resume"),
+            Some(JobStatus::Running)
+        );
+        // Paused before the worker got to it: queued again rather than resumed, the worker
+        // would otherwise skip starting it and its history entry
+        assert_eq!(
+            transition(JobStatus::Pending, false, false, "pause"),
+            Some(JobStatus::Paused)
+        );
+        assert_eq!(
+            transition(JobStatus::Paused, false, false, "resume"),
+            Some(JobStatus::Pending)
+        );
+        assert_eq!(
+            transition(JobStatus::Scheduled, true, true, "cancel"),
+            Some(JobStatus::Cancelled)
+        );
+        // A scheduled draft waits for its time once confirmed
+        assert_eq!(
+            transition(JobStatus::Draft, true, true, "confirm"),
+            Some(JobStatus::Scheduled)
+        );
+
+        // A paused job doesn't restart on a stale "confirm", a draft or a scheduled job is
+        // never sent by "resume"
+        assert_eq!(transition(JobStatus::Paused, false, true, "confirm"), None);
+        assert_eq!(transition(JobStatus::Running, false, true, "confirm"), None);
+        assert_eq!(transition(JobStatus::Draft, false, true, "resume"), None);
+        assert_eq!(transition(JobStatus::Scheduled, true, true, "resume"), None);
+        assert_eq!(
+            transition(JobStatus::Completed, false, true, "cancel"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_track_buttons() {
+        let markup = InlineKeyboardMarkup::new(vec![vec![
+            CallbackAction::Navigate("plastic".into()).button("Пластик"),
+            InlineKeyboardButton::url("Сайт", Url::parse("https://ecoklgd.ru").unwrap()),
+            CallbackAction::Navigate("x".repeat(55)).button("Длинная"),
+        ]])
+        .append_row(vec![InlineKeyboardButton::url(
+            "Карта",
+            Url::parse("https://ecoklgd.ru/map").unwrap(),
+        )]);
+
+        let links = link_buttons(&markup);
+        assert_eq!(
+            links,
+            vec![
+                UrlButton {
+                    text: "Сайт".into(),
+                    url: "https://ecoklgd.ru/".into(),
+                },
+                UrlButton {
+                    text: "Карта".into(),
+                    url: "https://ecoklgd.ru/map".into(),
+                },
+            ]
+        );
+
+        // The map link was added to the content after the broadcast started, the site link
+        // was typed by the admin without the trailing slash
+        let tracking = Tracking {
+            broadcast_id: "abc123".into(),
+            links: vec![UrlButton {
+                text: "Сайт".into(),
+                url: "https://ecoklgd.ru".into(),
+            }],
+        };
+        let tracked = track_buttons(markup, &tracking);
+        let row = &tracked.inline_keyboard[0];
+        assert_eq!(
+            row[0].kind,
+            InlineKeyboardButtonKind::CallbackData("1|t|abc123|n|plastic".to_string())
+        );
+        assert_eq!(
+            row[1].kind,
+            InlineKeyboardButtonKind::CallbackData("1|l|abc123|0".to_string())
+        );
+        assert_eq!(
+            row[2].kind,
+            InlineKeyboardButtonKind::CallbackData(format!("1|n|{}", "x".repeat(55)))
+        );
+        assert!(matches!(
+            tracked.inline_keyboard[1][0].kind,
+            InlineKeyboardButtonKind::Url(_)
+        ));
+    }
 }
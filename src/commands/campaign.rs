@@ -0,0 +1,449 @@
+use std::sync::Mutex;
+
+use chrono::Utc;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use teloxide::{
+    payloads::{SendMessageSetters, SendPhotoSetters},
+    prelude::Requester,
+    requests::Request,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode},
+    Bot,
+};
+
+use crate::audience::{Audience, AudienceFilter};
+use crate::broadcaster;
+use crate::callback::{CallbackAction, ConfirmScope};
+use crate::campaign::{campaign, parse_campaign_day, Campaign, CampaignDay, CAMPAIGNS};
+use crate::db::{self, CampaignPost, JobStatus};
+use crate::delivery::send_with_retry;
+use crate::media::send_media;
+
+use super::common::{confirmation_keyboard, load_page};
+use super::submission::report_keyboard;
+
+/// Confirmation message of the pending campaign preview
+static CONFIRMATION: Lazy<Mutex<Option<MessageId>>> = Lazy::new(|| Mutex::new(None));
+
+pub struct CampaignCommand;
+
+impl CampaignCommand {
+    /// Find a campaign by the key given to an admin command, listing the known keys otherwise
+    pub async fn lookup(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        key: Option<&str>,
+    ) -> Result<Option<&'static Campaign>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(campaign) = key.and_then(campaign) {
+            return Ok(Some(campaign));
+        }
+
+        let keys: Vec<&str> = CAMPAIGNS
+            .iter()
+            .map(|campaign| campaign.key.as_str())
+            .collect();
+        bot.send_message(
+            admin_chat_id,
+            format!("Укажите кампанию: {}", keys.join(", ")),
+        )
+        .await?;
+        Ok(None)
+    }
+
+    /// Load and parse the content file of a campaign day
+    fn load_content(path: &str) -> Result<String, String> {
+        load_page(path).map(|page| page.body).map_err(|e| {
+            error!("Failed to load {}: {}", path, e);
+            format!("Ошибка при загрузке {}", path)
+        })
+    }
+
+    /// Day the admin commands work with: the latest published one, with its content
+    fn current_day(campaign: &Campaign) -> Result<(CampaignDay, String), String> {
+        let day = campaign
+            .current(Utc::now())
+            .cloned()
+            .ok_or_else(|| format!("В кампании {} нет ни одного дня", campaign.key))?;
+        let content = Self::load_content(&day.content)?;
+        Ok((day, content))
+    }
+
+    /// Subscribers of a campaign who can still be reached, like a `sub:<key>` broadcast
+    fn audience(campaign: &Campaign) -> Audience {
+        Audience {
+            filters: vec![AudienceFilter::Subscription(campaign.key.clone())],
+        }
+    }
+
+    /// What is sent for a campaign day: its content under the day's image
+    fn post(campaign: &Campaign, day: &CampaignDay, content: String) -> CampaignPost {
+        CampaignPost {
+            campaign: campaign.key.clone(),
+            season: campaign.season.clone(),
+            day: day.day,
+            photo: day.image.clone(),
+            caption: content,
+        }
+    }
+
+    /// Send a campaign day to a single user
+    pub async fn send_to_user(
+        bot: &Bot,
+        user_id: i64,
+        post: &CampaignPost,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let buttons = report_keyboard(&post.campaign, post.day);
+        let caption = &post.caption;
+        match &post.photo {
+            Some(photo) => {
+                let buttons = &buttons;
+                send_media(photo, |photo| async move {
+                    send_with_retry(user_id, || {
+                        bot.send_photo(ChatId(user_id), photo.clone())
+                            .caption(caption)
+                            .parse_mode(ParseMode::Html)
+                            .reply_markup(buttons.clone())
+                            .send()
+                    })
+                    .await
+                })
+                .await?;
+            }
+            None => {
+                send_with_retry(user_id, || {
+                    bot.send_message(ChatId(user_id), caption)
+                        .disable_web_page_preview(true)
+                        .parse_mode(ParseMode::Html)
+                        .reply_markup(buttons.clone())
+                        .send()
+                })
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the current day of a campaign to a test user (admin command)
+    pub async fn send_test(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        test_user_id: i64,
+        key: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(campaign) = Self::lookup(bot, admin_chat_id, key).await? else {
+            return Ok(());
+        };
+        let (day, content) = match Self::current_day(campaign) {
+            Ok(loaded) => loaded,
+            Err(msg) => {
+                bot.send_message(admin_chat_id, msg).await?;
+                return Ok(());
+            }
+        };
+
+        info!(
+            "Sending test {} day {} to user {}",
+            campaign.key, day.day, test_user_id
+        );
+
+        let post = Self::post(campaign, &day, content);
+        match Self::send_to_user(bot, test_user_id, &post).await {
+            Ok(_) => {
+                bot.send_message(
+                    admin_chat_id,
+                    format!(
+                        "Тестовое сообщение отправлено пользователю {}",
+                        test_user_id
+                    ),
+                )
+                .await?;
+            }
+            Err(err) => {
+                error!("Failed to send test campaign message: {}", err);
+                bot.send_message(
+                    admin_chat_id,
+                    format!("Ошибка при отправке сообщения: {}", err),
+                )
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a day is being sent right now, and whether it reached the subscribers already
+    async fn delivery_state(campaign: &Campaign, day: u32) -> anyhow::Result<(bool, bool)> {
+        let jobs = db::get_campaign_broadcast_jobs(&campaign.key, &campaign.season, day).await?;
+        let sending = jobs
+            .iter()
+            .any(|job| !matches!(job.status, JobStatus::Cancelled | JobStatus::Completed));
+        let sent = jobs.iter().any(|job| job.status == JobStatus::Completed)
+            || db::get_delivered_campaign_days(&campaign.key, &campaign.season)
+                .await?
+                .contains(&day);
+        Ok((sending, sent))
+    }
+
+    /// Tell the admin why a day is not sent: it is being sent, or was sent and `force` is not
+    /// given. `true` when it can be sent
+    async fn check_delivery(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        campaign: &Campaign,
+        day: u32,
+        force: bool,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let text = match Self::delivery_state(campaign, day).await? {
+            (true, _) => format!(
+                "День {} кампании «{}» уже отправляется",
+                day, campaign.title
+            ),
+            (false, true) if !force => format!(
+                "День {} кампании «{}» уже отправлен. Отправить ещё раз: /campaign {} force",
+                day, campaign.title, campaign.key
+            ),
+            _ => return Ok(true),
+        };
+        bot.send_message(admin_chat_id, text).await?;
+        Ok(false)
+    }
+
+    /// Preview the current day of a campaign and ask the admin to confirm sending it. A day
+    /// that was sent already is only sent again with `force` (admin command)
+    pub async fn request_send_to_all(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        key: Option<&str>,
+        force: bool,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(campaign) = Self::lookup(bot, admin_chat_id, key).await? else {
+            return Ok(());
+        };
+        let (day, content) = match Self::current_day(campaign) {
+            Ok(loaded) => loaded,
+            Err(msg) => {
+                bot.send_message(admin_chat_id, msg).await?;
+                return Ok(());
+            }
+        };
+        if !Self::check_delivery(bot, admin_chat_id, campaign, day.day, force).await? {
+            return Ok(());
+        }
+        let users = Self::audience(campaign).resolve().await?;
+
+        let post = Self::post(campaign, &day, content);
+        if let Err(err) = Self::send_to_user(bot, admin_chat_id.0, &post).await {
+            bot.send_message(
+                admin_chat_id,
+                format!("Ошибка при отправке сообщения: {}", err),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let message = bot
+            .send_message(
+                admin_chat_id,
+                format!(
+                    "Отправить день {} кампании «{}» {}{} подписчикам?",
+                    day.day,
+                    campaign.title,
+                    if force { "повторно " } else { "" },
+                    users.len()
+                ),
+            )
+            .reply_markup(confirmation_keyboard(
+                &CallbackAction::Confirm {
+                    scope: ConfirmScope::Campaign,
+                    verb: if force { "resend" } else { "confirm" }.to_string(),
+                    id: format!("{}_{}", campaign.key, day.day),
+                },
+                &CallbackAction::Confirm {
+                    scope: ConfirmScope::Campaign,
+                    verb: "cancel".to_string(),
+                    id: String::new(),
+                },
+            ))
+            .await?;
+        *CONFIRMATION.lock().unwrap() = Some(message.id);
+
+        Ok(())
+    }
+
+    /// Handle the "Confirm" / "Cancel" buttons of the campaign preview, `id` being
+    /// `<key>_<day>` of the confirmed day, confirmed with "resend" when it was sent already
+    /// (admin only)
+    pub async fn control(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        verb: &str,
+        id: &str,
+        message_id: Option<MessageId>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Only the latest preview is valid, and only once
+        {
+            let mut pending = CONFIRMATION.lock().unwrap();
+            if message_id.is_none() || *pending != message_id {
+                return Ok(());
+            }
+            *pending = None;
+        }
+        let message_id = message_id.unwrap();
+
+        let confirmed = Some(id)
+            .filter(|_| verb == "confirm" || verb == "resend")
+            .and_then(parse_campaign_day)
+            .and_then(|(campaign, day)| Some((campaign, campaign.day(day)?)));
+        match confirmed {
+            Some((campaign, day)) => {
+                bot.edit_message_text(admin_chat_id, message_id, "Отправка подтверждена.")
+                    .await?;
+                Self::deliver_day(bot, admin_chat_id, campaign, day, verb == "resend").await
+            }
+            None => {
+                bot.edit_message_text(admin_chat_id, message_id, "Отправка отменена.")
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Queue a campaign day for its subscribers, again only when `force` is given. The
+    /// broadcast worker sends it and marks the day delivered once the job completes
+    pub async fn deliver_day(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        campaign: &'static Campaign,
+        day: &CampaignDay,
+        force: bool,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if !Self::check_delivery(bot, admin_chat_id, campaign, day.day, force).await? {
+            return Ok(());
+        }
+
+        let content = match Self::load_content(&day.content) {
+            Ok(c) => c,
+            Err(msg) => {
+                bot.send_message(admin_chat_id, msg).await?;
+                return Ok(());
+            }
+        };
+        let users = Self::audience(campaign).resolve().await?;
+
+        info!(
+            "Queueing {} day {} for {} users",
+            campaign.key,
+            day.day,
+            users.len()
+        );
+        let mut job = db::create_campaign_broadcast_job(
+            &day.content,
+            Self::post(campaign, day, content),
+            &Self::audience(campaign).to_string(),
+            admin_chat_id.0,
+            users,
+        )
+        .await?;
+        broadcaster::report_progress(bot, &mut job, None).await;
+        broadcaster::notify_new_job();
+
+        Ok(())
+    }
+
+    /// Offer the published days of the given campaign, or of every campaign the user is
+    /// subscribed to, as buttons, so late subscribers can catch up
+    pub async fn offer_catch_up(
+        bot: &Bot,
+        chat_id: ChatId,
+        key: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let campaigns: Vec<&Campaign> = match key {
+            Some(key) => campaign(key).into_iter().collect(),
+            None => {
+                let subscriptions = db::get_user_subscriptions(chat_id.0).await?;
+                CAMPAIGNS
+                    .iter()
+                    .filter(|campaign| subscriptions.contains(&campaign.key))
+                    .collect()
+            }
+        };
+
+        let now = Utc::now();
+        let mut sent = false;
+        for campaign in campaigns {
+            let buttons: Vec<Vec<InlineKeyboardButton>> = campaign
+                .published(now)
+                .map(|day| {
+                    CallbackAction::CatchUp {
+                        campaign: campaign.key.clone(),
+                        day: day.day,
+                    }
+                    .button(format!("День {}", day.day))
+                })
+                .collect::<Vec<_>>()
+                .chunks(4)
+                .map(|row| row.to_vec())
+                .collect();
+            if buttons.is_empty() {
+                continue;
+            }
+
+            bot.send_message(chat_id, format!("Прошедшие задания «{}»:", campaign.title))
+                .reply_markup(InlineKeyboardMarkup::new(buttons))
+                .await?;
+            sent = true;
+        }
+
+        if !sent {
+            bot.send_message(chat_id, "Прошедших заданий пока нет.")
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send a published day requested from the catch-up buttons
+    pub async fn send_past_day(
+        bot: &Bot,
+        user_id: i64,
+        key: &str,
+        day: u32,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let requested = campaign(key)
+            .and_then(|campaign| Some((campaign, campaign.published_day(day, Utc::now())?)));
+        let Some((campaign, day)) = requested else {
+            bot.send_message(ChatId(user_id), "Это задание ещё не открыто.")
+                .await?;
+            return Ok(());
+        };
+
+        let content = Self::load_content(&day.content)?;
+        Self::send_to_user(bot, user_id, &Self::post(campaign, day, content)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::Assets;
+
+    #[test]
+    fn test_load_content() {
+        // This will test that every campaign day, image and opt-out text can be loaded
+        for campaign in CAMPAIGNS.iter() {
+            for day in &campaign.days {
+                let result = CampaignCommand::load_content(&day.content);
+                assert!(result.is_ok(), "{} should be loadable", day.content);
+                if let Some(image) = &day.image {
+                    assert!(
+                        image.starts_with("https://") || Assets::get(image).is_some(),
+                        "{} should be in src/assets",
+                        image
+                    );
+                }
+            }
+            assert!(CampaignCommand::load_content(&campaign.opt_out).is_ok());
+        }
+    }
+}
@@ -1,7 +1,15 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
 use rust_embed::RustEmbed;
 use teloxide::types::InlineKeyboardMarkup;
 
-use crate::route::build_buttons_with_user;
+use crate::callback::CallbackAction;
+use crate::contents::{self, ContentSet};
+use crate::page::Page;
+use crate::route::{breadcrumb_line, build_buttons_in};
 
 #[derive(RustEmbed)]
 #[folder = "src/contents/"]
@@ -10,6 +18,59 @@ pub struct Contents;
 pub const ADMIN_ID: i64 = 283564928;
 pub const TEST_USER_ID: i64 = 108609383;
 
+/// "Confirm" / "Cancel" keyboard for admin actions that need a second step
+pub fn confirmation_keyboard(
+    confirm: &CallbackAction,
+    cancel: &CallbackAction,
+) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        confirm.button("✅ Подтвердить"),
+        cancel.button("✖️ Отменить"),
+    ]])
+}
+
+/// Chats whose next message answers a question of the bot, forgotten after `ttl` so a
+/// question left unanswered doesn't swallow later messages
+pub struct Awaiting<T> {
+    ttl: Duration,
+    chats: Mutex<HashMap<i64, (T, Instant)>>,
+}
+
+impl<T: Clone> Awaiting<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Awaiting {
+            ttl,
+            chats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn insert(&self, chat_id: i64, value: T) {
+        self.chats
+            .lock()
+            .unwrap()
+            .insert(chat_id, (value, Instant::now()));
+    }
+
+    /// What the chat is asked for, unless the question expired
+    pub fn get(&self, chat_id: i64) -> Option<T> {
+        let mut chats = self.chats.lock().unwrap();
+        match chats.get(&chat_id) {
+            Some((_, asked_at)) if asked_at.elapsed() >= self.ttl => {
+                chats.remove(&chat_id);
+                None
+            }
+            Some((value, _)) => Some(value.clone()),
+            None => None,
+        }
+    }
+
+    /// Stop waiting for the chat, returning what it was asked for unless the question expired
+    pub fn remove(&self, chat_id: i64) -> Option<T> {
+        let (value, asked_at) = self.chats.lock().unwrap().remove(&chat_id)?;
+        (asked_at.elapsed() < self.ttl).then_some(value)
+    }
+}
+
 pub fn build_details(
     text: &str,
     is_external: bool,
@@ -22,16 +83,63 @@ pub fn build_details_with_user(
     is_external: bool,
     user_id: Option<i64>,
 ) -> Result<(InlineKeyboardMarkup, String), Box<dyn std::error::Error + Send + Sync>> {
-    let route = text.trim_start_matches('/').replace("/", "-");
-    let file_name = format!("{}.md", &route);
-    let content = Contents::get(&file_name)
-        .ok_or_else(|| format!("File {} not found", file_name))?
-        .data;
+    let (buttons, page) = build_page_with_user(text, is_external, user_id)?;
+    Ok((buttons, page.body))
+}
 
-    let content = String::from_utf8(content.to_vec())?;
-    let buttons = build_buttons_with_user(&route, is_external, user_id);
+/// Page of a content file, as currently loaded
+pub fn load_page(file_name: &str) -> Result<Page, String> {
+    let route = file_name.strip_suffix(".md").unwrap_or(file_name);
+    contents::current()
+        .pages
+        .get(route)
+        .cloned()
+        .ok_or_else(|| format!("File {} not found", file_name))
+}
 
-    Ok((buttons, content))
+/// Buttons and content of a route, with the media and settings from its front-matter
+pub fn build_page_with_user(
+    text: &str,
+    is_external: bool,
+    user_id: Option<i64>,
+) -> Result<(InlineKeyboardMarkup, Page), Box<dyn std::error::Error + Send + Sync>> {
+    build_menu_page(text, is_external, user_id, 0)
+}
+
+/// Buttons and content of a route, showing the given page of its children
+pub fn build_menu_page(
+    text: &str,
+    is_external: bool,
+    user_id: Option<i64>,
+    menu_page: usize,
+) -> Result<(InlineKeyboardMarkup, Page), Box<dyn std::error::Error + Send + Sync>> {
+    let route = text.trim_start_matches('/');
+    let content = contents::current();
+    let mut page = content
+        .pages
+        .get(route)
+        .cloned()
+        .ok_or_else(|| format!("File {}.md not found", route))?;
+    if !page.is_available(Utc::now()) {
+        return Err("Эта страница сейчас недоступна.".into());
+    }
+    // Broadcasts stand on their own, the path only helps inside the menu
+    if !is_external {
+        add_breadcrumb(&content, route, &mut page);
+    }
+    let buttons = build_buttons_in(&content, route, is_external, user_id, menu_page);
+
+    Ok((buttons, page))
+}
+
+/// Put the path from the main menu above the text of a nested page
+pub fn add_breadcrumb(content: &ContentSet, route: &str, page: &mut Page) {
+    if page.hide_breadcrumb {
+        return;
+    }
+    if let Some(line) = breadcrumb_line(content, route) {
+        page.body = format!("{}\n\n{}", page.parse_mode.italic(&line), page.body);
+    }
 }
 
 #[cfg(test)]
@@ -43,4 +151,32 @@ mod tests {
         let result = build_details("start", false);
         assert!(result.is_ok(), "start.md should be loadable");
     }
+
+    #[test]
+    fn test_awaiting_expires() {
+        let awaiting = Awaiting::new(Duration::from_secs(60));
+        awaiting.insert(1, "plastic");
+        assert_eq!(awaiting.get(1), Some("plastic"));
+        assert_eq!(awaiting.remove(1), Some("plastic"));
+        assert_eq!(awaiting.get(1), None);
+
+        let expired = Awaiting::new(Duration::ZERO);
+        expired.insert(1, "plastic");
+        assert_eq!(expired.get(1), None);
+        assert_eq!(expired.remove(1), None);
+    }
+
+    #[test]
+    fn test_add_breadcrumb() {
+        let content = contents::current();
+        let mut plastic = content.pages["plastic"].clone();
+        add_breadcrumb(&content, "plastic", &mut plastic);
+        assert!(plastic
+            .body
+            .starts_with("<i>Главная › ♻️ Переработка › Пластик</i>\n\n"));
+
+        let mut recycling = content.pages["recycling"].clone();
+        add_breadcrumb(&content, "recycling", &mut recycling);
+        assert_eq!(recycling.body, content.pages["recycling"].body);
+    }
 }
@@ -0,0 +1,203 @@
+use log::info;
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use teloxide::{
+    payloads::CopyMessageSetters,
+    prelude::Requester,
+    types::{ChatId, Message, MessageId},
+    Bot,
+};
+
+use crate::audience::{Audience, AUDIENCE_HELP};
+use crate::broadcaster;
+use crate::db::{self, CopiedMessage, UrlButton};
+
+use super::broadcast::url_buttons;
+
+const BUTTONS_HELP: &str = "Пришлите кнопки-ссылки, по одной в строке:\n\
+Текст кнопки | https://example.com\n\
+или «-», если кнопки не нужны.";
+
+/// Admin's progress through composing a broadcast message
+#[derive(Debug, Default)]
+enum ComposeState {
+    #[default]
+    Idle,
+    AwaitingMessage,
+    AwaitingButtons(CopiedMessage),
+    Ready(CopiedMessage),
+}
+
+static STATE: Lazy<Mutex<ComposeState>> = Lazy::new(|| Mutex::new(ComposeState::default()));
+
+/// Parse `Text | https://url` lines into URL buttons
+pub fn parse_buttons(text: &str) -> Result<Vec<UrlButton>, String> {
+    if text.trim() == "-" {
+        return Ok(vec![]);
+    }
+
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (label, url) = line
+                .split_once('|')
+                .ok_or_else(|| format!("Нет «|» в строке: {}", line))?;
+            let (label, url) = (label.trim(), url.trim());
+            if label.is_empty() || reqwest::Url::parse(url).is_err() {
+                return Err(format!("Неверная кнопка: {}", line));
+            }
+            Ok(UrlButton {
+                text: label.to_string(),
+                url: url.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub struct ComposeCommand;
+
+impl ComposeCommand {
+    /// Start composing a broadcast message (admin command)
+    pub async fn start(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        *STATE.lock().unwrap() = ComposeState::AwaitingMessage;
+        bot.send_message(
+            admin_chat_id,
+            "Пришлите сообщение для рассылки: текст, фото, видео или документ.",
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Whether the next admin message belongs to the compose flow
+    pub fn is_composing() -> bool {
+        matches!(
+            *STATE.lock().unwrap(),
+            ComposeState::AwaitingMessage | ComposeState::AwaitingButtons(_)
+        )
+    }
+
+    /// Take the admin message as the broadcast content or as its buttons
+    pub async fn handle_message(
+        bot: &Bot,
+        msg: &Message,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let state = std::mem::take(&mut *STATE.lock().unwrap());
+        match state {
+            ComposeState::AwaitingMessage => {
+                let draft = CopiedMessage {
+                    chat_id: msg.chat.id.0,
+                    message_id: msg.id.0,
+                    buttons: vec![],
+                };
+                *STATE.lock().unwrap() = ComposeState::AwaitingButtons(draft);
+                bot.send_message(msg.chat.id, BUTTONS_HELP).await?;
+            }
+            ComposeState::AwaitingButtons(mut draft) => {
+                match parse_buttons(msg.text().unwrap_or_default()) {
+                    Ok(buttons) => draft.buttons = buttons,
+                    Err(err) => {
+                        *STATE.lock().unwrap() = ComposeState::AwaitingButtons(draft);
+                        bot.send_message(msg.chat.id, format!("{}\n\n{}", err, BUTTONS_HELP))
+                            .await?;
+                        return Ok(());
+                    }
+                }
+
+                Self::preview(bot, msg.chat.id, &draft).await?;
+                *STATE.lock().unwrap() = ComposeState::Ready(draft);
+                bot.send_message(
+                    msg.chat.id,
+                    "Так сообщение увидят получатели.\nОтправить: /senddraft [аудитория]\nСоставить заново: /compose",
+                )
+                .await?;
+            }
+            state => *STATE.lock().unwrap() = state,
+        }
+        Ok(())
+    }
+
+    /// Copy the draft back to the admin exactly as recipients will get it
+    async fn preview(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        draft: &CopiedMessage,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let request = bot.copy_message(
+            admin_chat_id,
+            ChatId(draft.chat_id),
+            MessageId(draft.message_id),
+        );
+        match url_buttons(&draft.buttons)? {
+            Some(buttons) => request.reply_markup(buttons).await?,
+            None => request.await?,
+        };
+        Ok(())
+    }
+
+    /// Ask for confirmation to broadcast the composed message to an audience (admin command)
+    pub async fn send_draft(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        audience: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let draft = match &*STATE.lock().unwrap() {
+            ComposeState::Ready(draft) => Some(draft.clone()),
+            _ => None,
+        };
+        let Some(draft) = draft else {
+            bot.send_message(admin_chat_id, "Нет готового сообщения. Начните с /compose")
+                .await?;
+            return Ok(());
+        };
+
+        let audience = match audience.parse::<Audience>() {
+            Ok(audience) => audience,
+            Err(err) => {
+                bot.send_message(admin_chat_id, format!("{}\n\n{}", err, AUDIENCE_HELP))
+                    .await?;
+                return Ok(());
+            }
+        };
+        let users = audience.resolve().await?;
+        info!(
+            "Broadcasting composed message {} to {} users of audience {}",
+            draft.message_id,
+            users.len(),
+            audience
+        );
+
+        let mut job = db::create_broadcast_job(
+            "",
+            Some(draft),
+            &audience.to_string(),
+            admin_chat_id.0,
+            users,
+        )
+        .await?;
+        *STATE.lock().unwrap() = ComposeState::Idle;
+        broadcaster::report_progress(bot, &mut job, None).await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_buttons() {
+        let buttons =
+            parse_buttons("Сайт | https://ecoklgd.ru\nКанал|https://t.me/ecoklgd").unwrap();
+        assert_eq!(buttons.len(), 2);
+        assert_eq!(buttons[1].text, "Канал");
+        assert_eq!(buttons[1].url, "https://t.me/ecoklgd");
+
+        assert!(parse_buttons("-").unwrap().is_empty());
+        assert!(parse_buttons("Сайт https://ecoklgd.ru").is_err());
+        assert!(parse_buttons("Сайт | not a url").is_err());
+    }
+}
@@ -1,14 +1,86 @@
+use log::warn;
 use teloxide::{
-    payloads::SendMessageSetters,
+    payloads::{
+        EditMessageTextSetters, SendDocumentSetters, SendMessageSetters, SendPhotoSetters,
+        SendVideoSetters,
+    },
     prelude::Requester,
-    types::{ChatId, ParseMode},
-    Bot,
+    requests::Request,
+    types::{
+        ChatId, InlineKeyboardMarkup, InputFile, InputMedia, InputMediaDocument, InputMediaPhoto,
+        InputMediaVideo, Message,
+    },
+    ApiError, Bot, RequestError,
 };
 
-use super::common::{build_details, build_details_with_user};
+use crate::contents::ContentSet;
+use crate::media::{send_album, send_media};
+use crate::page::{MediaKind, Page};
+use crate::route::build_buttons_in;
+
+use super::common::{add_breadcrumb, build_menu_page, build_page_with_user};
 
 pub struct ContentCommand;
 
+/// Send one file, with the page text as its caption when given
+async fn send_file(
+    bot: &Bot,
+    chat_id: ChatId,
+    kind: MediaKind,
+    file: InputFile,
+    caption: Option<(&Page, InlineKeyboardMarkup)>,
+) -> Result<Message, RequestError> {
+    let Some((page, buttons)) = caption else {
+        return match kind {
+            MediaKind::Photo => bot.send_photo(chat_id, file).await,
+            MediaKind::Video => bot.send_video(chat_id, file).await,
+            MediaKind::Document => bot.send_document(chat_id, file).await,
+        };
+    };
+
+    let parse_mode = page.parse_mode.parse_mode();
+    match kind {
+        MediaKind::Photo => {
+            let mut request = bot
+                .send_photo(chat_id, file)
+                .caption(&page.body)
+                .reply_markup(buttons);
+            if let Some(parse_mode) = parse_mode {
+                request = request.parse_mode(parse_mode);
+            }
+            request.await
+        }
+        MediaKind::Video => {
+            let mut request = bot
+                .send_video(chat_id, file)
+                .caption(&page.body)
+                .reply_markup(buttons);
+            if let Some(parse_mode) = parse_mode {
+                request = request.parse_mode(parse_mode);
+            }
+            request.await
+        }
+        MediaKind::Document => {
+            let mut request = bot
+                .send_document(chat_id, file)
+                .caption(&page.body)
+                .reply_markup(buttons);
+            if let Some(parse_mode) = parse_mode {
+                request = request.parse_mode(parse_mode);
+            }
+            request.await
+        }
+    }
+}
+
+fn album_item(kind: MediaKind, file: InputFile) -> InputMedia {
+    match kind {
+        MediaKind::Photo => InputMedia::Photo(InputMediaPhoto::new(file)),
+        MediaKind::Video => InputMedia::Video(InputMediaVideo::new(file)),
+        MediaKind::Document => InputMedia::Document(InputMediaDocument::new(file)),
+    }
+}
+
 impl ContentCommand {
     /// Send content for a route (used by menu commands like /start, /about, etc.)
     pub async fn send(
@@ -16,15 +88,8 @@ impl ContentCommand {
         chat_id: ChatId,
         route: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let (buttons, content) = build_details(route, false)?;
-
-        bot.send_message(chat_id, content)
-            .disable_web_page_preview(true)
-            .parse_mode(ParseMode::Html)
-            .reply_markup(buttons)
-            .await?;
-
-        Ok(())
+        let (buttons, page) = build_page_with_user(route, false, None)?;
+        Self::deliver(bot, chat_id, buttons, &page).await
     }
 
     /// Send content for a route with user-specific buttons
@@ -34,13 +99,115 @@ impl ContentCommand {
         route: &str,
         user_id: i64,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let (buttons, content) = build_details_with_user(route, false, Some(user_id))?;
+        let (buttons, page) = build_page_with_user(route, false, Some(user_id))?;
+        Self::deliver(bot, chat_id, buttons, &page).await
+    }
+
+    /// Open a route, on the given page of its children, from a menu button by editing the
+    /// clicked message in place. Pages with media can't replace a text message, so they, and
+    /// failed edits, go out as a new message
+    pub async fn navigate(
+        bot: &Bot,
+        clicked: Option<&Message>,
+        route: &str,
+        menu_page: usize,
+        user_id: i64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (buttons, page) = build_menu_page(route, false, Some(user_id), menu_page)?;
+        let chat_id = ChatId(user_id);
+
+        let Some(message) = clicked.filter(|message| message.text().is_some()) else {
+            return Self::deliver(bot, chat_id, buttons, &page).await;
+        };
+        if !page.media.is_empty() {
+            return Self::deliver(bot, chat_id, buttons, &page).await;
+        }
+
+        let mut request = bot
+            .edit_message_text(message.chat.id, message.id, &page.body)
+            .disable_web_page_preview(true)
+            .reply_markup(buttons.clone());
+        if let Some(parse_mode) = page.parse_mode.parse_mode() {
+            request = request.parse_mode(parse_mode);
+        }
+        match request.await {
+            // Pressing the button of the page already shown
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+            Err(e) => {
+                warn!("Failed to edit message {} in place: {:?}", message.id, e);
+                Self::deliver(bot, chat_id, buttons, &page).await
+            }
+        }
+    }
+
+    /// Send a page as it would look within a content set that is not live yet (admin preview)
+    pub async fn preview(
+        bot: &Bot,
+        chat_id: ChatId,
+        content: &ContentSet,
+        route: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut page = content
+            .pages
+            .get(route)
+            .cloned()
+            .ok_or_else(|| format!("Route {} not found", route))?;
+        add_breadcrumb(content, route, &mut page);
+        let buttons = build_buttons_in(content, route, false, Some(chat_id.0), 0);
+        Self::deliver(bot, chat_id, buttons, &page).await
+    }
+
+    /// Send a page: a single file carries the text as its caption when it fits, otherwise
+    /// the files go first and the text follows with the buttons
+    async fn deliver(
+        bot: &Bot,
+        chat_id: ChatId,
+        buttons: InlineKeyboardMarkup,
+        page: &Page,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match page.media.as_slice() {
+            [] => {}
+            [media] if page.fits_caption() => {
+                send_media(&media.file, |file| {
+                    send_file(
+                        bot,
+                        chat_id,
+                        media.kind,
+                        file,
+                        Some((page, buttons.clone())),
+                    )
+                })
+                .await?;
+                return Ok(());
+            }
+            [media] => {
+                send_media(&media.file, |file| {
+                    send_file(bot, chat_id, media.kind, file, None)
+                })
+                .await?;
+            }
+            album => {
+                let names: Vec<&str> = album.iter().map(|media| media.file.as_str()).collect();
+                send_album(&names, |files| {
+                    let items = album
+                        .iter()
+                        .zip(files)
+                        .map(|(media, file)| album_item(media.kind, file))
+                        .collect::<Vec<_>>();
+                    bot.send_media_group(chat_id, items).send()
+                })
+                .await?;
+            }
+        }
 
-        bot.send_message(chat_id, content)
+        let mut request = bot
+            .send_message(chat_id, &page.body)
             .disable_web_page_preview(true)
-            .parse_mode(ParseMode::Html)
-            .reply_markup(buttons)
-            .await?;
+            .reply_markup(buttons);
+        if let Some(parse_mode) = page.parse_mode.parse_mode() {
+            request = request.parse_mode(parse_mode);
+        }
+        request.await?;
 
         Ok(())
     }
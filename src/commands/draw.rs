@@ -0,0 +1,197 @@
+use chrono::Utc;
+use log::{error, info};
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use teloxide::{prelude::Requester, requests::Request, types::ChatId, Bot};
+
+use crate::db::{self, CampaignDraw};
+use crate::delivery::send_with_retry;
+
+use super::campaign::CampaignCommand;
+use super::submission::leaderboard;
+
+const DRAW_USAGE: &str =
+    "Формат: /draw <кампания> <победителей> <минимум заданий> [seed:<число>] [сообщение]\n\
+Seed повторяет прошлый розыгрыш, без него выбирается случайный.";
+
+/// Arguments of the /draw command
+#[derive(Debug, PartialEq)]
+pub struct DrawArgs {
+    pub count: usize,
+    pub min_tasks: usize,
+    pub seed: Option<u64>,
+    pub message: Option<String>,
+}
+
+/// Split off the first word, returning it and the rest without leading whitespace
+fn split_word(text: &str) -> (&str, &str) {
+    let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    (word, rest.trim_start())
+}
+
+/// Parse `<count> <min tasks> [seed:<n>] [message]`
+pub fn parse_draw_args(args: &str) -> Option<DrawArgs> {
+    let (count, rest) = split_word(args.trim_start());
+    let count = count.parse().ok().filter(|count| *count > 0)?;
+    let (min_tasks, mut rest) = split_word(rest);
+    let min_tasks = min_tasks.parse().ok()?;
+
+    let mut seed = None;
+    let (word, tail) = split_word(rest);
+    if let Some(value) = word.strip_prefix("seed:") {
+        seed = Some(value.parse().ok()?);
+        rest = tail;
+    }
+    let message = Some(rest.trim())
+        .filter(|message| !message.is_empty())
+        .map(str::to_string);
+
+    Some(DrawArgs {
+        count,
+        min_tasks,
+        seed,
+        message,
+    })
+}
+
+/// Pick up to `count` winners with a reproducible shuffle of the participants
+pub fn draw_winners(participants: &[i64], count: usize, seed: u64) -> Vec<i64> {
+    // Sorted first, so the result depends only on the seed and the set of participants
+    let mut participants = participants.to_vec();
+    participants.sort_unstable();
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    participants
+        .choose_multiple(&mut rng, count)
+        .copied()
+        .collect()
+}
+
+pub struct DrawCommand;
+
+impl DrawCommand {
+    /// Draw winners among campaign participants with enough approved tasks and notify them (admin command)
+    pub async fn handle(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        args: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (key, args) = split_word(args.trim_start());
+        let Some(campaign) = CampaignCommand::lookup(bot, admin_chat_id, Some(key)).await? else {
+            return Ok(());
+        };
+        let Some(args) = parse_draw_args(args) else {
+            bot.send_message(admin_chat_id, DRAW_USAGE).await?;
+            return Ok(());
+        };
+
+        let submissions = db::get_campaign_submissions(&campaign.key, &campaign.season).await?;
+        let participants: Vec<i64> = leaderboard(&submissions)
+            .into_iter()
+            .filter(|(_, tasks)| *tasks >= args.min_tasks)
+            .map(|(user_id, _)| user_id)
+            .collect();
+        if participants.is_empty() {
+            bot.send_message(
+                admin_chat_id,
+                format!("Нет участников с {} и более заданиями.", args.min_tasks),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let seed = args.seed.unwrap_or_else(rand::random);
+        let winners = draw_winners(&participants, args.count, seed);
+        info!(
+            "{} draw with seed {}: {} of {} participants with {}+ tasks won: {:?}",
+            campaign.key,
+            seed,
+            winners.len(),
+            participants.len(),
+            args.min_tasks,
+            winners
+        );
+        db::create_campaign_draw(CampaignDraw {
+            campaign: campaign.key.clone(),
+            season: campaign.season.clone(),
+            seed,
+            min_tasks: args.min_tasks,
+            count: args.count,
+            participants: participants.clone(),
+            winners: winners.clone(),
+            drawn_at: Utc::now(),
+        })
+        .await?;
+
+        let message = args.message.as_deref().unwrap_or(&campaign.winner_message);
+        let mut failed = vec![];
+        for user_id in &winners {
+            let user_id = *user_id;
+            if let Err(e) = send_with_retry(user_id, || {
+                bot.send_message(ChatId(user_id), message).send()
+            })
+            .await
+            {
+                error!("Failed to notify winner {}: {:?}", user_id, e);
+                failed.push(user_id.to_string());
+            }
+        }
+
+        let winners: Vec<String> = winners.iter().map(|id| id.to_string()).collect();
+        let mut report = format!(
+            "Розыгрыш среди {} участников ({}+ заданий), seed {}.\nПобедители: {}",
+            participants.len(),
+            args.min_tasks,
+            seed,
+            winners.join(", ")
+        );
+        if !failed.is_empty() {
+            report.push_str(&format!("\nНе удалось уведомить: {}", failed.join(", ")));
+        }
+        bot.send_message(admin_chat_id, report).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_draw_args() {
+        assert_eq!(
+            parse_draw_args("3 10 seed:42 Вы победили!\nЖдите приз."),
+            Some(DrawArgs {
+                count: 3,
+                min_tasks: 10,
+                seed: Some(42),
+                message: Some("Вы победили!\nЖдите приз.".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_draw_args("1 5"),
+            Some(DrawArgs {
+                count: 1,
+                min_tasks: 5,
+                seed: None,
+                message: None,
+            })
+        );
+        assert_eq!(parse_draw_args("0 5"), None);
+        assert_eq!(parse_draw_args("3"), None);
+        assert_eq!(parse_draw_args("3 10 seed:abc"), None);
+    }
+
+    #[test]
+    fn test_draw_winners() {
+        let participants = vec![5, 1, 4, 2, 3];
+        let winners = draw_winners(&participants, 2, 42);
+        assert_eq!(winners.len(), 2);
+        assert!(winners.iter().all(|id| participants.contains(id)));
+
+        // Reproducible regardless of the order participants were loaded in
+        assert_eq!(draw_winners(&[1, 2, 3, 4, 5], 2, 42), winners);
+        assert_eq!(draw_winners(&participants, 10, 7).len(), 5);
+    }
+}
@@ -0,0 +1,347 @@
+use std::time::Duration;
+
+use log::{error, info};
+use once_cell::sync::Lazy;
+use teloxide::{
+    net::Download,
+    payloads::{SendDocumentSetters, SendMessageSetters},
+    prelude::Requester,
+    types::{ChatId, InlineKeyboardMarkup, InputFile, Message},
+    Bot,
+};
+
+use crate::callback::{CallbackAction, ConfirmScope};
+use crate::contents;
+use crate::db;
+use crate::local_time::format_local_datetime;
+
+use super::common::{confirmation_keyboard, Awaiting};
+use super::content::ContentCommand;
+
+/// Published versions listed by /versions
+const VERSIONS_LIMIT: usize = 10;
+/// Longest source sent as a message, longer ones go as a file
+const MESSAGE_LIMIT: usize = 4096;
+/// How long the bot waits for the new source after /edit
+const EDIT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Admin chats whose next message is the new source of a route
+static AWAITING: Lazy<Awaiting<String>> = Lazy::new(|| Awaiting::new(EDIT_TIMEOUT));
+
+/// Editing button: `publish`, `discard` or `rollback` of a version, `reset` of a route, or
+/// `cancel` of the edit waiting for its source
+fn edit_action(verb: &str, id: &str) -> CallbackAction {
+    CallbackAction::Confirm {
+        scope: ConfirmScope::Content,
+        verb: verb.to_string(),
+        id: id.to_string(),
+    }
+}
+
+pub struct EditCommand;
+
+impl EditCommand {
+    /// Route known to the current content, telling the admin how to name one otherwise
+    async fn lookup(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        route: Option<&str>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let route = route.map(|route| route.trim_start_matches('/'));
+        if let Some(route) = route.filter(|route| contents::current().pages.contains_key(*route)) {
+            return Ok(Some(route.to_string()));
+        }
+
+        bot.send_message(
+            admin_chat_id,
+            "Укажите страницу, например: /source plastic, /edit plastic или /versions plastic",
+        )
+        .await?;
+        Ok(None)
+    }
+
+    /// Send the markdown source of a page, front-matter included (admin command)
+    pub async fn source(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        route: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(route) = Self::lookup(bot, admin_chat_id, route).await? else {
+            return Ok(());
+        };
+        let source = contents::current().sources[&route].clone();
+
+        // No parse mode: the admin sees the markup itself, ready to copy and edit
+        if source.chars().count() <= MESSAGE_LIMIT {
+            bot.send_message(admin_chat_id, source).await?;
+        } else {
+            bot.send_document(
+                admin_chat_id,
+                InputFile::memory(source.into_bytes()).file_name(format!("{}.md", route)),
+            )
+            .caption("Страница длиннее одного сообщения, её текст в файле.")
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Ask for the new source of a page (admin command)
+    pub async fn request(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        route: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(route) = Self::lookup(bot, admin_chat_id, route).await? else {
+            return Ok(());
+        };
+
+        bot.send_message(
+            admin_chat_id,
+            format!(
+                "Пришлите новый текст страницы {} целиком, вместе с front-matter, сообщением или .md файлом. \
+Текущий текст: /source {}. Отменить: /cancel",
+                route, route
+            ),
+        )
+        .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+            edit_action("cancel", "").button("✖️ Отменить"),
+        ]]))
+        .await?;
+        AWAITING.insert(admin_chat_id.0, route);
+        Ok(())
+    }
+
+    /// Whether the next message of the admin is the new source of a page
+    pub fn is_awaiting(chat_id: i64) -> bool {
+        AWAITING.get(chat_id).is_some()
+    }
+
+    /// Stop waiting for the new source of a page (admin command and button)
+    pub async fn cancel(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let text = match AWAITING.remove(admin_chat_id.0) {
+            Some(route) => format!("Редактирование страницы {} отменено.", route),
+            None => "Нечего отменять.".to_string(),
+        };
+        bot.send_message(admin_chat_id, text).await?;
+        Ok(())
+    }
+
+    /// Text of a message or of its attached file
+    async fn message_source(
+        bot: &Bot,
+        msg: &Message,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(text) = msg.text() {
+            return Ok(Some(text.to_string()));
+        }
+        let Some(document) = msg.document() else {
+            return Ok(None);
+        };
+
+        let file = bot.get_file(&document.file.id).await?;
+        let mut data = vec![];
+        bot.download_file(&file.path, &mut data).await?;
+        Ok(String::from_utf8(data).ok())
+    }
+
+    /// Validate the new source, store it as a draft and show the admin a preview to publish
+    pub async fn handle_message(
+        bot: &Bot,
+        msg: &Message,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(route) = AWAITING.get(msg.chat.id.0) else {
+            return Ok(());
+        };
+        let Some(source) = Self::message_source(bot, msg).await? else {
+            bot.send_message(msg.chat.id, "Пришлите текст страницы или .md файл в UTF-8.")
+                .await?;
+            return Ok(());
+        };
+
+        // Stays awaiting on errors, so a corrected text can follow right away
+        let content = match contents::check_edit(&route, Some(&source)) {
+            Ok(content) => content,
+            Err(problems) => {
+                bot.send_message(
+                    msg.chat.id,
+                    format!(
+                        "{}\n\nИсправьте текст и пришлите его снова.",
+                        contents::describe_problems(&problems)
+                    ),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+        AWAITING.remove(msg.chat.id.0);
+
+        let version =
+            db::create_content_version(&route, Some(source), msg.chat.id.0, false).await?;
+        info!("Draft {} of page {} created", version.id, route);
+
+        if let Err(e) = ContentCommand::preview(bot, msg.chat.id, &content, &route).await {
+            error!("Failed to preview draft of page {}: {:?}", route, e);
+            bot.send_message(
+                msg.chat.id,
+                format!("Не удалось показать страницу: {:?}", e),
+            )
+            .await?;
+        }
+        let record_id = version.record_id();
+        bot.send_message(
+            msg.chat.id,
+            format!("Так будет выглядеть страница {}. Опубликовать?", route),
+        )
+        .reply_markup(confirmation_keyboard(
+            &edit_action("publish", &record_id),
+            &edit_action("discard", &record_id),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    /// List the published versions of a page with buttons to roll back to them (admin command)
+    pub async fn versions(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        route: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(route) = Self::lookup(bot, admin_chat_id, route).await? else {
+            return Ok(());
+        };
+        let versions = db::get_content_versions(&route, VERSIONS_LIMIT).await?;
+        if versions.is_empty() {
+            bot.send_message(
+                admin_chat_id,
+                format!(
+                    "Страница {} не редактировалась, используется её файл.",
+                    route
+                ),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let mut lines = vec![format!("Версии страницы {}, новые сверху:", route)];
+        let mut buttons = vec![];
+        for (index, version) in versions.iter().enumerate() {
+            let published_at = version.published_at.unwrap_or(version.created_at);
+            let label = match &version.source {
+                Some(_) => format!("{}. {}", index + 1, format_local_datetime(published_at)),
+                None => format!(
+                    "{}. {} (файл)",
+                    index + 1,
+                    format_local_datetime(published_at)
+                ),
+            };
+            lines.push(format!(
+                "{}, автор {}{}",
+                label,
+                version.author,
+                if index == 0 {
+                    " — текущая"
+                } else {
+                    ""
+                }
+            ));
+            if index > 0 && version.source.is_some() {
+                buttons.push(vec![edit_action("rollback", &version.record_id())
+                    .button(format!("↩️ Вернуть {}", label))]);
+            }
+        }
+        if versions[0].source.is_some() {
+            buttons.push(vec![
+                edit_action("reset", &route).button("↩️ Вернуть файл страницы")
+            ]);
+        }
+
+        bot.send_message(admin_chat_id, lines.join("\n"))
+            .reply_markup(InlineKeyboardMarkup::new(buttons))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish or discard a draft, or roll a page back from the editing buttons. `id` is
+    /// the version, or the route for `reset` (admin only)
+    pub async fn control(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        verb: &str,
+        id: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match verb {
+            "reset" => return Self::publish(bot, admin_chat_id, id, None).await,
+            "cancel" => return Self::cancel(bot, admin_chat_id).await,
+            _ => {}
+        }
+
+        let record_id = id;
+        let Some(version) = db::get_content_version(record_id).await? else {
+            bot.send_message(admin_chat_id, "Версия не найдена").await?;
+            return Ok(());
+        };
+
+        match verb {
+            "publish" => {
+                if version.published_at.is_some() {
+                    bot.send_message(admin_chat_id, "Эта версия уже опубликована")
+                        .await?;
+                    return Ok(());
+                }
+                // Checked again: the files or other pages may have changed since the preview
+                if let Err(problems) =
+                    contents::apply_edit(&version.route, version.source.as_deref())
+                {
+                    bot.send_message(admin_chat_id, contents::describe_problems(&problems))
+                        .await?;
+                    return Ok(());
+                }
+                db::publish_content_version(record_id).await?;
+                info!("Draft {} of page {} published", record_id, version.route);
+                bot.send_message(
+                    admin_chat_id,
+                    format!("Страница {} опубликована.", version.route),
+                )
+                .await?;
+            }
+            "discard" => {
+                if version.published_at.is_none() {
+                    db::delete_content_version(record_id).await?;
+                }
+                bot.send_message(admin_chat_id, "Черновик удалён.").await?;
+            }
+            "rollback" => {
+                Self::publish(bot, admin_chat_id, &version.route, version.source).await?;
+            }
+            _ => return Err(format!("Unknown content edit action: {}", verb).into()),
+        }
+
+        Ok(())
+    }
+
+    /// Make a source live right away as a new version, `None` going back to the file of the page
+    async fn publish(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        route: &str,
+        source: Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Err(problems) = contents::apply_edit(route, source.as_deref()) {
+            bot.send_message(admin_chat_id, contents::describe_problems(&problems))
+                .await?;
+            return Ok(());
+        }
+        let version = db::create_content_version(route, source, admin_chat_id.0, true).await?;
+        info!("Page {} rolled back as version {}", route, version.id);
+
+        bot.send_message(admin_chat_id, format!("Страница {} возвращена.", route))
+            .await?;
+        Ok(())
+    }
+}
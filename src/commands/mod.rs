@@ -1,15 +1,32 @@
-pub mod advent;
 pub mod broadcast;
+pub mod campaign;
 pub mod common;
+pub mod compose;
 pub mod content;
+pub mod draw;
+pub mod edit;
 pub mod location;
+pub mod reload;
+pub mod schedule;
+pub mod stats;
 pub mod stop;
+pub mod submission;
 pub mod subscription;
 
-pub use advent::AdventCommand;
 pub use broadcast::BroadcastCommand;
-pub use common::{build_details, build_details_with_user, ADMIN_ID, TEST_USER_ID};
+pub use campaign::CampaignCommand;
+pub use common::{
+    build_details, build_details_with_user, build_page_with_user, confirmation_keyboard, ADMIN_ID,
+    TEST_USER_ID,
+};
+pub use compose::ComposeCommand;
 pub use content::ContentCommand;
+pub use draw::DrawCommand;
+pub use edit::EditCommand;
 pub use location::LocationCommand;
+pub use reload::ReloadCommand;
+pub use schedule::ScheduleCommand;
+pub use stats::StatsCommand;
 pub use stop::StopCommand;
+pub use submission::SubmissionCommand;
 pub use subscription::SubscriptionCommand;
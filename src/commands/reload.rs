@@ -0,0 +1,36 @@
+use log::{error, info};
+use teloxide::{prelude::Requester, types::ChatId, Bot};
+
+use crate::contents;
+
+pub struct ReloadCommand;
+
+impl ReloadCommand {
+    /// Reload content pages, keeping the current ones when the new set fails validation (admin command)
+    pub async fn handle(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let message = match contents::reload() {
+            Ok(count) => {
+                info!("Content reloaded by admin: {} pages", count);
+                match contents::content_dir() {
+                    Some(dir) => {
+                        format!("Контент обновлён из {}: {} страниц.", dir.display(), count)
+                    }
+                    None => format!(
+                        "CONTENT_DIR не задан, используется встроенный контент: {} страниц.",
+                        count
+                    ),
+                }
+            }
+            Err(problems) => {
+                error!("Content reload rejected: {}", problems.join("; "));
+                contents::describe_problems(&problems)
+            }
+        };
+        bot.send_message(admin_chat_id, message).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use chrono::Utc;
+use log::info;
+use teloxide::{prelude::Requester, types::ChatId, Bot};
+
+use crate::audience::{Audience, AUDIENCE_HELP};
+use crate::broadcaster;
+use crate::db::{self, JobStatus};
+use crate::local_time::{format_local_datetime, parse_local_datetime};
+
+use super::broadcast::BroadcastCommand;
+
+const SCHEDULE_USAGE: &str =
+    "Формат: /schedule <маршрут> <ГГГГ-ММ-ДД> <ЧЧ:ММ> [аудитория]\nВремя калининградское.";
+
+pub struct ScheduleCommand;
+
+impl ScheduleCommand {
+    /// Schedule a broadcast of a route at a Kaliningrad date and time (admin command)
+    pub async fn schedule(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        args: &[&str],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some((route, rest)) = args.split_first() else {
+            bot.send_message(admin_chat_id, SCHEDULE_USAGE).await?;
+            return Ok(());
+        };
+
+        // Date and time may come as two words or as one `2025-12-01T10:00` word
+        let (scheduled_at, audience) = match rest {
+            [date, time, audience @ ..]
+                if parse_local_datetime(&format!("{} {}", date, time)).is_some() =>
+            {
+                (
+                    parse_local_datetime(&format!("{} {}", date, time)),
+                    audience,
+                )
+            }
+            [datetime, audience @ ..] => (parse_local_datetime(datetime), audience),
+            [] => (None, rest),
+        };
+        let Some(scheduled_at) = scheduled_at else {
+            bot.send_message(admin_chat_id, SCHEDULE_USAGE).await?;
+            return Ok(());
+        };
+
+        if scheduled_at <= Utc::now() {
+            bot.send_message(admin_chat_id, "Время рассылки уже прошло.")
+                .await?;
+            return Ok(());
+        }
+        let audience = match audience.join(" ").parse::<Audience>() {
+            Ok(audience) => audience,
+            Err(err) => {
+                bot.send_message(admin_chat_id, format!("{}\n\n{}", err, AUDIENCE_HELP))
+                    .await?;
+                return Ok(());
+            }
+        };
+        // Previewed and confirmed now, like an immediate broadcast: nobody is asked when it starts
+        if let Err(err) = BroadcastCommand::send_to_user(bot, admin_chat_id.0, route, None).await {
+            bot.send_message(
+                admin_chat_id,
+                format!("Ошибка маршрута {}: {:?}", route, err),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let mut job = db::create_scheduled_broadcast_job(
+            route,
+            &audience.to_string(),
+            admin_chat_id.0,
+            scheduled_at,
+        )
+        .await?;
+        info!(
+            "Broadcast of {} at {} awaits confirmation",
+            route,
+            format_local_datetime(scheduled_at)
+        );
+        broadcaster::report_progress(bot, &mut job, None).await;
+
+        Ok(())
+    }
+
+    /// List upcoming scheduled broadcasts (admin command)
+    pub async fn list(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let jobs = db::get_scheduled_broadcast_jobs().await?;
+        if jobs.is_empty() {
+            bot.send_message(admin_chat_id, "Запланированных рассылок нет.")
+                .await?;
+            return Ok(());
+        }
+
+        let lines: Vec<String> = jobs
+            .iter()
+            .map(|job| {
+                format!(
+                    "{} – {} («{}»), id {}",
+                    job.scheduled_at
+                        .map(format_local_datetime)
+                        .unwrap_or_default(),
+                    job.route,
+                    job.audience,
+                    job.record_id()
+                )
+            })
+            .collect();
+
+        bot.send_message(
+            admin_chat_id,
+            format!("Запланированные рассылки:\n{}", lines.join("\n")),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Cancel a scheduled broadcast that has not started yet (admin command)
+    pub async fn cancel(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        record_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let job = match db::get_broadcast_job(record_id).await? {
+            Some(job) if job.status == JobStatus::Scheduled => job,
+            _ => {
+                bot.send_message(
+                    admin_chat_id,
+                    format!("Запланированная рассылка {} не найдена.", record_id),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
+        db::update_broadcast_job_status(&job, JobStatus::Cancelled).await?;
+        info!("Scheduled broadcast {} cancelled by admin", job.id);
+
+        bot.send_message(
+            admin_chat_id,
+            format!("Рассылка {} ({}) отменена.", record_id, job.route),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use chrono::{Duration, Utc};
+use log::{error, info};
+use reqwest::Url;
+use teloxide::{
+    payloads::SendMessageSetters,
+    prelude::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::db;
+use crate::local_time::format_local_datetime;
+
+pub struct StatsCommand;
+
+impl StatsCommand {
+    /// Send user activity statistics (admin command)
+    pub async fn handle(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let now = Utc::now();
+        let total = db::get_all_users().await?.len();
+        let active = db::get_active_users().await?.len();
+        let day = db::count_users_seen_since(now - Duration::days(1)).await?;
+        let week = db::count_users_seen_since(now - Duration::days(7)).await?;
+        let month = db::count_users_seen_since(now - Duration::days(30)).await?;
+
+        bot.send_message(
+            admin_chat_id,
+            format!(
+                "Пользователей: {}\nНе заблокировано: {}\nАктивны за сутки: {}\nАктивны за неделю: {}\nАктивны за месяц: {}",
+                total, active, day, week, month
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// User with the given username, telling the admin when there is none
+    async fn lookup(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        username: Option<&str>,
+    ) -> Result<Option<db::User>, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(username) = username else {
+            bot.send_message(
+                admin_chat_id,
+                "Укажите имя пользователя, например: /user @name или /message @name Текст",
+            )
+            .await?;
+            return Ok(None);
+        };
+
+        let user = db::get_user_by_username(username).await?;
+        if user.is_none() {
+            bot.send_message(
+                admin_chat_id,
+                format!("Пользователь {} не найден", username),
+            )
+            .await?;
+        }
+        Ok(user)
+    }
+
+    /// Profile and activity of a user found by username (admin command)
+    pub async fn user(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        username: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(user) = Self::lookup(bot, admin_chat_id, username).await? else {
+            return Ok(());
+        };
+
+        let last_seen = user
+            .last_seen_at
+            .map(format_local_datetime)
+            .unwrap_or_else(|| "неизвестно".to_string());
+        let subscriptions = if user.subscriptions.is_empty() {
+            "нет".to_string()
+        } else {
+            user.subscriptions.join(", ")
+        };
+        let text = format!(
+            "{} (@{}), ID {}\nЯзык: {}\nPremium: {}\nПоследняя активность: {}\nСообщений: {}, нажатий: {}\nПодписки: {}\nЗаблокировал бота: {}",
+            user.first_name.as_deref().unwrap_or("Без имени"),
+            user.username.as_deref().unwrap_or_default(),
+            user.user_id,
+            user.language_code.as_deref().unwrap_or("неизвестен"),
+            if user.is_premium { "да" } else { "нет" },
+            last_seen,
+            user.message_count,
+            user.callback_count,
+            subscriptions,
+            if user.blacklisted { "да" } else { "нет" },
+        );
+
+        let mut request = bot.send_message(admin_chat_id, text);
+        if let Some(url) = user
+            .username
+            .as_deref()
+            .and_then(|username| Url::parse(&format!("https://t.me/{}", username)).ok())
+        {
+            request = request.reply_markup(InlineKeyboardMarkup::new(vec![vec![
+                InlineKeyboardButton::url("Открыть чат", url),
+            ]]));
+        }
+        request.await?;
+
+        Ok(())
+    }
+
+    /// Send a text from the bot to a user found by username (admin command)
+    pub async fn message(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        username: Option<&str>,
+        text: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(user) = Self::lookup(bot, admin_chat_id, username).await? else {
+            return Ok(());
+        };
+        if text.trim().is_empty() {
+            bot.send_message(admin_chat_id, "Добавьте текст после имени пользователя")
+                .await?;
+            return Ok(());
+        }
+
+        match bot.send_message(ChatId(user.user_id), text.trim()).await {
+            Ok(_) => {
+                info!("Admin message sent to user {}", user.user_id);
+                bot.send_message(admin_chat_id, "Сообщение отправлено")
+                    .await?;
+            }
+            Err(e) => {
+                error!(
+                    "Failed to send admin message to user {}: {:?}",
+                    user.user_id, e
+                );
+                bot.send_message(
+                    admin_chat_id,
+                    format!("Ошибка при отправке сообщения: {:?}", e),
+                )
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
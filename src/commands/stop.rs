@@ -6,44 +6,54 @@ use teloxide::{
     Bot,
 };
 
+use crate::campaign::CAMPAIGNS;
 use crate::db;
 
-use super::common::Contents;
+use super::common::load_page;
 
 pub struct StopCommand;
 
 impl StopCommand {
+    /// Unsubscribe the user from everything, with the opt-out text of every campaign they left
     pub async fn handle(
         bot: &Bot,
         chat_id: ChatId,
         user_id: i64,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let subscriptions = db::get_user_subscriptions(user_id)
+            .await
+            .unwrap_or_default();
+
         match db::unsubscribe_all(user_id).await {
             Ok(true) => {
-                let content = match Contents::get("unsubscribe_advent.md") {
-                    Some(file) => match String::from_utf8(file.data.to_vec()) {
-                        Ok(content) => content,
+                let opt_outs: Vec<&str> = CAMPAIGNS
+                    .iter()
+                    .filter(|campaign| subscriptions.contains(&campaign.key))
+                    .map(|campaign| campaign.opt_out.as_str())
+                    .collect();
+                if opt_outs.is_empty() {
+                    bot.send_message(chat_id, "Вы отписались от всех рассылок.")
+                        .await?;
+                }
+
+                for path in opt_outs {
+                    let content = match load_page(path) {
+                        Ok(page) => page.body,
                         Err(e) => {
-                            error!("Failed to parse unsubscribe_advent.md: {:?}", e);
+                            error!("Failed to load {}: {}", path, e);
                             bot.send_message(
                                 chat_id,
-                                "Ошибка при загрузке файла unsubscribe_advent.md",
+                                format!("Ошибка при загрузке файла {}", path),
                             )
                             .await?;
-                            return Ok(());
+                            continue;
                         }
-                    },
-                    None => {
-                        error!("unsubscribe_advent.md not found");
-                        bot.send_message(chat_id, "Файл unsubscribe_advent.md не найден")
-                            .await?;
-                        return Ok(());
-                    }
-                };
+                    };
 
-                bot.send_message(chat_id, content)
-                    .parse_mode(ParseMode::Html)
-                    .await?;
+                    bot.send_message(chat_id, content)
+                        .parse_mode(ParseMode::Html)
+                        .await?;
+                }
             }
             Ok(false) => {
                 bot.send_message(chat_id, "Вы не подписаны ни на одну рассылку.")
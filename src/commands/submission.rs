@@ -0,0 +1,333 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use once_cell::sync::Lazy;
+use teloxide::{
+    payloads::SendMessageSetters,
+    prelude::Requester,
+    types::{ChatId, InlineKeyboardMarkup, Message, MessageId},
+    Bot,
+};
+
+use crate::callback::{CallbackAction, ConfirmScope};
+use crate::campaign::{campaign, Campaign};
+use crate::db::{self, CampaignSubmission, SubmissionStatus};
+
+use super::common::{Awaiting, ADMIN_ID};
+
+/// How long the bot waits for a report after the "report" button
+const REPORT_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Users whose next message is a report, with the campaign day it belongs to
+static AWAITING: Lazy<Awaiting<(&'static Campaign, u32)>> =
+    Lazy::new(|| Awaiting::new(REPORT_TIMEOUT));
+
+/// Button under a campaign day that starts a report on it
+pub fn report_keyboard(campaign: &str, day: u32) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![CallbackAction::Report {
+        campaign: campaign.to_string(),
+        day,
+    }
+    .button("📝 Отправить отчёт")]])
+}
+
+/// Participants by the number of days with an approved report, best first
+pub fn leaderboard(submissions: &[CampaignSubmission]) -> Vec<(i64, usize)> {
+    let mut days: HashMap<i64, BTreeSet<u32>> = HashMap::new();
+    for submission in submissions {
+        if submission.status == SubmissionStatus::Approved {
+            days.entry(submission.user_id)
+                .or_default()
+                .insert(submission.day);
+        }
+    }
+
+    let mut board: Vec<(i64, usize)> = days
+        .into_iter()
+        .map(|(user_id, days)| (user_id, days.len()))
+        .collect();
+    board.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    board
+}
+
+pub struct SubmissionCommand;
+
+impl SubmissionCommand {
+    /// Ask the user for a report on a published campaign day
+    pub async fn request(
+        bot: &Bot,
+        user_id: i64,
+        key: &str,
+        day: u32,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let requested = campaign(key)
+            .and_then(|campaign| Some((campaign, campaign.published_day(day, Utc::now())?)));
+        let Some((campaign, day)) = requested else {
+            bot.send_message(ChatId(user_id), "Это задание ещё не открыто.")
+                .await?;
+            return Ok(());
+        };
+
+        AWAITING.insert(user_id, (campaign, day.day));
+        bot.send_message(
+            ChatId(user_id),
+            format!(
+                "Пришлите отчёт о задании дня {}: ссылку, фото или текст.",
+                day.day
+            ),
+        )
+        .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+            CallbackAction::CancelReport.button("✖️ Отменить"),
+        ]]))
+        .await?;
+        Ok(())
+    }
+
+    /// Whether the next message of the user is a report
+    pub fn is_awaiting(user_id: i64) -> bool {
+        AWAITING.get(user_id).is_some()
+    }
+
+    /// Stop waiting for a report, e.g. when the user opens the menu instead
+    pub fn forget(user_id: i64) {
+        AWAITING.remove(user_id);
+    }
+
+    /// Handle the "Cancel" button under the report request
+    pub async fn cancel(
+        bot: &Bot,
+        user_id: i64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let text = match AWAITING.remove(user_id) {
+            Some(_) => "Отчёт отменён.",
+            None => "Бот уже не ждёт отчёта.",
+        };
+        bot.send_message(ChatId(user_id), text).await?;
+        Ok(())
+    }
+
+    /// Store the user's message as a report and pass it to the admin for review
+    pub async fn handle_message(
+        bot: &Bot,
+        msg: &Message,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let user_id = msg.chat.id.0;
+        let Some((campaign, day)) = AWAITING.remove(user_id) else {
+            return Ok(());
+        };
+
+        let text = msg.text().or(msg.caption()).map(str::to_string);
+        let photo_file_id = msg
+            .photo()
+            .and_then(|sizes| sizes.last())
+            .map(|photo| photo.file.id.clone());
+        if text.is_none() && photo_file_id.is_none() {
+            AWAITING.insert(user_id, (campaign, day));
+            bot.send_message(
+                msg.chat.id,
+                "Отчёт должен содержать ссылку, фото или текст.",
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let submission = db::create_campaign_submission(
+            &campaign.key,
+            &campaign.season,
+            day,
+            user_id,
+            text,
+            photo_file_id,
+            msg.id.0,
+        )
+        .await?;
+        info!(
+            "Submission {} for {} day {} from user {}",
+            submission.id, campaign.key, day, user_id
+        );
+
+        bot.send_message(
+            msg.chat.id,
+            "Спасибо! Отчёт отправлен на проверку, мы сообщим о результате.",
+        )
+        .await?;
+
+        let admin_chat_id = ChatId(ADMIN_ID);
+        bot.copy_message(admin_chat_id, msg.chat.id, msg.id).await?;
+        let review = |verb: &str| CallbackAction::Confirm {
+            scope: ConfirmScope::Submission,
+            verb: verb.to_string(),
+            id: submission.record_id(),
+        };
+        bot.send_message(
+            admin_chat_id,
+            format!(
+                "Отчёт пользователя {} за день {} кампании «{}»",
+                user_id, day, campaign.title
+            ),
+        )
+        .reply_markup(InlineKeyboardMarkup::new(vec![vec![
+            review("approve").button("✅ Принять"),
+            review("reject").button("❌ Отклонить"),
+        ]]))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Handle the "Approve" / "Reject" buttons of a report (admin only)
+    pub async fn review(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        verb: &str,
+        record_id: &str,
+        message_id: Option<MessageId>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let status = match verb {
+            "approve" => SubmissionStatus::Approved,
+            "reject" => SubmissionStatus::Rejected,
+            _ => return Err(format!("Unknown submission action: {}", verb).into()),
+        };
+
+        let Some(submission) = db::get_campaign_submission(record_id).await? else {
+            bot.send_message(admin_chat_id, "Отчёт не найден").await?;
+            return Ok(());
+        };
+        if submission.status != SubmissionStatus::Pending {
+            bot.send_message(admin_chat_id, "Отчёт уже проверен")
+                .await?;
+            return Ok(());
+        }
+
+        db::review_campaign_submission(record_id, status).await?;
+        info!("Submission {} set to {:?}", submission.id, status);
+
+        let (admin_note, user_note) = match status {
+            SubmissionStatus::Approved => ("принят", "принят! 🎉"),
+            _ => ("отклонён", "отклонён. Можно прислать новый отчёт."),
+        };
+        if let Some(message_id) = message_id {
+            bot.edit_message_text(
+                admin_chat_id,
+                message_id,
+                format!(
+                    "Отчёт пользователя {} за день {} {}.",
+                    submission.user_id, submission.day, admin_note
+                ),
+            )
+            .await?;
+        }
+        if let Err(e) = bot
+            .send_message(
+                ChatId(submission.user_id),
+                format!("Ваш отчёт за день {} {}", submission.day, user_note),
+            )
+            .await
+        {
+            error!(
+                "Failed to notify user {} about the review: {:?}",
+                submission.user_id, e
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Show the participants of a campaign ranked by approved reports (admin command)
+    pub async fn leaderboard(
+        bot: &Bot,
+        admin_chat_id: ChatId,
+        campaign: &Campaign,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let submissions = db::get_campaign_submissions(&campaign.key, &campaign.season).await?;
+        let board = leaderboard(&submissions);
+        if board.is_empty() {
+            bot.send_message(admin_chat_id, "Принятых отчётов пока нет.")
+                .await?;
+            return Ok(());
+        }
+
+        let names: HashMap<i64, String> = db::get_user_records()
+            .await?
+            .into_iter()
+            .filter_map(|user| {
+                let name = user
+                    .username
+                    .map(|username| format!("@{}", username))
+                    .or(user.first_name)?;
+                Some((user.user_id, name))
+            })
+            .collect();
+        let pending = submissions
+            .iter()
+            .filter(|submission| submission.status == SubmissionStatus::Pending)
+            .count();
+
+        let lines: Vec<String> = board
+            .iter()
+            .enumerate()
+            .map(|(place, (user_id, days))| {
+                let name = names
+                    .get(user_id)
+                    .cloned()
+                    .unwrap_or_else(|| user_id.to_string());
+                format!("{}. {} ({}) – {}", place + 1, name, user_id, days)
+            })
+            .collect();
+
+        bot.send_message(
+            admin_chat_id,
+            format!(
+                "Выполненные задания «{}»:\n{}\n\nНа проверке: {}",
+                campaign.title,
+                lines.join("\n"),
+                pending
+            ),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(user_id: i64, day: u32, status: SubmissionStatus) -> CampaignSubmission {
+        CampaignSubmission {
+            id: (
+                "campaign_submission",
+                format!("{}_{}", user_id, day).as_str(),
+            )
+                .into(),
+            campaign: "advent".to_string(),
+            season: "test".to_string(),
+            day,
+            user_id,
+            text: Some("https://vk.com/wall1".to_string()),
+            photo_file_id: None,
+            message_id: 1,
+            status,
+            submitted_at: Utc::now(),
+            reviewed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_leaderboard() {
+        let submissions = vec![
+            submission(1, 1, SubmissionStatus::Approved),
+            // A second approved report on the same day does not count twice
+            submission(1, 1, SubmissionStatus::Approved),
+            submission(2, 1, SubmissionStatus::Approved),
+            submission(2, 2, SubmissionStatus::Approved),
+            submission(3, 1, SubmissionStatus::Rejected),
+            submission(3, 2, SubmissionStatus::Pending),
+        ];
+
+        assert_eq!(leaderboard(&submissions), vec![(2, 2), (1, 1)]);
+    }
+}
@@ -2,12 +2,14 @@ use log::error;
 use teloxide::{
     payloads::SendMessageSetters,
     prelude::Requester,
-    types::{ParseMode, UserId},
+    types::{ChatId, ParseMode, UserId},
     Bot,
 };
 
+use crate::campaign::campaign;
 use crate::db;
 
+use super::campaign::CampaignCommand;
 use super::common::build_details;
 
 pub struct SubscriptionCommand;
@@ -17,18 +19,26 @@ impl SubscriptionCommand {
         bot: &Bot,
         user_id: UserId,
         subscription_type: &str,
-        callback_text: &str,
     ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
         let user_id_i64: i64 = user_id.0.try_into().unwrap();
 
         match db::subscribe_user(user_id_i64, subscription_type).await {
             Ok(true) => {
-                let (buttons, content) = build_details(callback_text, false)?;
+                let (buttons, content) =
+                    build_details(&format!("subscribe_{}", subscription_type), false)?;
                 bot.send_message(user_id, content)
                     .disable_web_page_preview(true)
                     .parse_mode(ParseMode::Html)
                     .reply_markup(buttons)
                     .await?;
+                if campaign(subscription_type).is_some() {
+                    CampaignCommand::offer_catch_up(
+                        bot,
+                        ChatId(user_id_i64),
+                        Some(subscription_type),
+                    )
+                    .await?;
+                }
                 Ok(true)
             }
             Ok(false) => {
@@ -49,13 +59,13 @@ impl SubscriptionCommand {
         bot: &Bot,
         user_id: UserId,
         subscription_type: &str,
-        callback_text: &str,
     ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
         let user_id_i64: i64 = user_id.0.try_into().unwrap();
 
         match db::unsubscribe_user(user_id_i64, subscription_type).await {
             Ok(true) => {
-                let (buttons, content) = build_details(callback_text, false)?;
+                let (buttons, content) =
+                    build_details(&format!("unsubscribe_{}", subscription_type), false)?;
                 bot.send_message(user_id, content)
                     .disable_web_page_preview(true)
                     .parse_mode(ParseMode::Html)
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use log::{error, info};
+use once_cell::sync::Lazy;
+use teloxide::{prelude::Requester, types::ChatId, Bot};
+
+use crate::commands::common::{Contents, ADMIN_ID};
+use crate::db;
+use crate::page::Page;
+use crate::route::{routes_from_pages, Route};
+use crate::validation::validate_pages;
+
+/// How often `CONTENT_DIR` is checked for edited, added or removed files
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// Validation problems listed in one report, the rest are only counted
+const PROBLEMS_SHOWN: usize = 20;
+
+/// Pages and the route tree derived from them, swapped as a whole on reload
+pub struct ContentSet {
+    pub pages: HashMap<String, Page>,
+    pub routes: HashMap<String, Route>,
+    /// Markdown source of every page, front-matter included
+    pub sources: HashMap<String, String>,
+}
+
+impl ContentSet {
+    pub fn new(sources: HashMap<String, String>) -> Result<Self, Vec<String>> {
+        let pages = parse_sources(&sources).map_err(|e| vec![e])?;
+        let routes = routes_from_pages(&pages);
+        Ok(ContentSet {
+            pages,
+            routes,
+            sources,
+        })
+    }
+}
+
+static CONTENT: Lazy<RwLock<Arc<ContentSet>>> =
+    Lazy::new(|| RwLock::new(Arc::new(initial_content())));
+/// Sources of the pages edited from Telegram, on top of the files. Also serializes reloads
+static EDITED: Lazy<RwLock<HashMap<String, String>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Content in use right now. Hold on to it for one message, so a reload can't mix two versions
+pub fn current() -> Arc<ContentSet> {
+    CONTENT.read().unwrap().clone()
+}
+
+/// Directory of markdown files overriding the embedded ones, from `CONTENT_DIR`
+pub fn content_dir() -> Option<PathBuf> {
+    env::var("CONTENT_DIR")
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+/// Source of every embedded `src/contents/*.md` file, keyed by its route
+pub fn embedded_sources() -> Result<HashMap<String, String>, String> {
+    Contents::iter()
+        .filter_map(|file| {
+            let route = file.strip_suffix(".md")?.to_string();
+            let data = Contents::get(&file)?.data;
+            Some(
+                String::from_utf8(data.to_vec())
+                    .map(|source| (route, source))
+                    .map_err(|e| format!("{}: {}", file, e)),
+            )
+        })
+        .collect()
+}
+
+/// Parse every embedded `src/contents/*.md` file, keyed by its route
+pub fn load_embedded() -> Result<HashMap<String, Page>, String> {
+    parse_sources(&embedded_sources()?)
+}
+
+/// Source of every `*.md` file of a directory, keyed by its route
+pub fn dir_sources(dir: &Path) -> Result<HashMap<String, String>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+
+    let mut sources = HashMap::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .path();
+        let Some(route) = markdown_route(&path) else {
+            continue;
+        };
+        let source =
+            std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        sources.insert(route, source);
+    }
+    Ok(sources)
+}
+
+fn parse_sources(sources: &HashMap<String, String>) -> Result<HashMap<String, Page>, String> {
+    sources
+        .iter()
+        .map(|(route, source)| {
+            Page::parse(source)
+                .map(|page| (route.clone(), page))
+                .map_err(|e| format!("{}: {}", route, e))
+        })
+        .collect()
+}
+
+/// Route of a markdown file, `None` for anything else
+fn markdown_route(path: &Path) -> Option<String> {
+    if path.extension()? != "md" {
+        return None;
+    }
+    Some(path.file_stem()?.to_str()?.to_string())
+}
+
+/// Embedded pages with the files of `dir` on top, and the pages edited from Telegram on top of
+/// both. A file on disk replaces the embedded page of the same route, and a removed file brings
+/// the embedded one back
+pub fn load(
+    dir: Option<&Path>,
+    edited: &HashMap<String, String>,
+) -> Result<ContentSet, Vec<String>> {
+    let mut sources = embedded_sources().map_err(|e| vec![e])?;
+    if let Some(dir) = dir {
+        sources.extend(dir_sources(dir).map_err(|e| vec![e])?);
+    }
+    sources.extend(edited.clone());
+
+    let content = ContentSet::new(sources)?;
+    let problems = validate_pages(&content.pages);
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    Ok(content)
+}
+
+fn initial_content() -> ContentSet {
+    if let Some(dir) = content_dir() {
+        match load(Some(&dir), &HashMap::new()) {
+            Ok(content) => {
+                info!("Content loaded from {}", dir.display());
+                return content;
+            }
+            Err(problems) => error!(
+                "Content of {} rejected, using the embedded content: {}",
+                dir.display(),
+                problems.join("; ")
+            ),
+        }
+    }
+    let sources = embedded_sources().expect("Embedded content should be readable");
+    ContentSet::new(sources).expect("Embedded content should be valid")
+}
+
+/// Load and validate the content again, swapping it in only when the whole set is consistent.
+/// Returns the number of pages now in use
+pub fn reload() -> Result<usize, Vec<String>> {
+    let edited = EDITED.write().unwrap();
+    swap(load(content_dir().as_deref(), &edited)?)
+}
+
+fn swap(content: ContentSet) -> Result<usize, Vec<String>> {
+    let count = content.pages.len();
+    *CONTENT.write().unwrap() = Arc::new(content);
+    Ok(count)
+}
+
+/// Content as it would be with the source of a page replaced, `None` going back to its file
+pub fn check_edit(route: &str, source: Option<&str>) -> Result<ContentSet, Vec<String>> {
+    let mut edited = EDITED.read().unwrap().clone();
+    match source {
+        Some(source) => edited.insert(route.to_string(), source.to_string()),
+        None => edited.remove(route),
+    };
+    load(content_dir().as_deref(), &edited)
+}
+
+/// Replace the source of a page edited from Telegram, `None` going back to its file.
+/// Nothing changes when the resulting set fails validation
+pub fn apply_edit(route: &str, source: Option<&str>) -> Result<usize, Vec<String>> {
+    let mut edited = EDITED.write().unwrap();
+    let mut candidate = edited.clone();
+    match source {
+        Some(source) => candidate.insert(route.to_string(), source.to_string()),
+        None => candidate.remove(route),
+    };
+
+    let count = swap(load(content_dir().as_deref(), &candidate)?)?;
+    *edited = candidate;
+    Ok(count)
+}
+
+/// Put the pages published from Telegram on top of the files, once the database is connected
+pub async fn load_edited() -> anyhow::Result<()> {
+    let edited: HashMap<String, String> = db::get_live_content_versions()
+        .await?
+        .into_iter()
+        .filter_map(|version| Some((version.route, version.source?)))
+        .collect();
+    let count = edited.len();
+
+    // Kept out of EDITED when they conflict with the files, so reloads still work
+    let mut current = EDITED.write().unwrap();
+    load(content_dir().as_deref(), &edited)
+        .and_then(swap)
+        .map_err(|problems| anyhow::anyhow!(problems.join("; ")))?;
+    *current = edited;
+    info!("{} pages edited from Telegram loaded", count);
+    Ok(())
+}
+
+/// Validation problems as a message for the admin
+pub fn describe_problems(problems: &[String]) -> String {
+    let mut message = format!(
+        "Контент не обновлён, оставлена прежняя версия:\n{}",
+        problems
+            .iter()
+            .take(PROBLEMS_SHOWN)
+            .map(|problem| format!("• {}", problem))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    if problems.len() > PROBLEMS_SHOWN {
+        message.push_str(&format!("\n…и ещё {}", problems.len() - PROBLEMS_SHOWN));
+    }
+    message
+}
+
+/// Modification times of the markdown files in the directory
+fn fingerprint(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| markdown_route(&entry.path()).is_some())
+        .map(|entry| {
+            let modified = entry.metadata().and_then(|meta| meta.modified()).ok();
+            (entry.path(), modified)
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Start the background task that reloads the content whenever a file in `CONTENT_DIR` changes,
+/// telling the admin when the changed set is rejected
+pub fn spawn_watcher(bot: Bot) {
+    let Some(dir) = content_dir() else {
+        return;
+    };
+    info!("Watching {} for content changes", dir.display());
+
+    tokio::spawn(async move {
+        let mut last = fingerprint(&dir);
+        loop {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            let files = fingerprint(&dir);
+            if files == last {
+                continue;
+            }
+            last = files;
+
+            match reload() {
+                Ok(count) => info!("Content reloaded from {}: {} pages", dir.display(), count),
+                Err(problems) => {
+                    error!("Content change rejected: {}", problems.join("; "));
+                    if let Err(e) = bot
+                        .send_message(ChatId(ADMIN_ID), describe_problems(&problems))
+                        .await
+                    {
+                        error!("Failed to report rejected content: {:?}", e);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_dir_overrides_embedded() {
+        let dir = env::temp_dir().join(format!("ecobot-contents-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("faq.md"),
+            "---\nlabel: Вопросы\nparent: start\n---\nНовый текст",
+        )
+        .unwrap();
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let content = load(Some(&dir), &HashMap::new()).unwrap();
+        assert_eq!(content.pages["faq"].body.trim(), "Новый текст");
+        assert_eq!(content.routes["faq"].label, "Вопросы");
+        assert!(content.pages.contains_key("plastic"));
+        assert!(!content.pages.contains_key("notes"));
+
+        // Pages edited from Telegram win over the files
+        let edited = HashMap::from([(
+            "faq".to_string(),
+            "---\nlabel: Вопросы\nparent: start\n---\nПравка".to_string(),
+        )]);
+        let content = load(Some(&dir), &edited).unwrap();
+        assert_eq!(content.pages["faq"].body.trim(), "Правка");
+        assert_eq!(content.sources["faq"], edited["faq"]);
+
+        std::fs::write(
+            dir.join("faq.md"),
+            "---\nlabel: Вопросы\nparent: missing\n---\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load(Some(&dir), &HashMap::new()).err(),
+            Some(vec!["faq: parent missing does not exist".to_string()])
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -112,12 +112,12 @@ pub async fn store_esso_points() -> Result<bool> {
     let result = features
         .into_iter()
         .map(|feature| {
-            return serde_json::json!({
+            serde_json::json!({
                 "latitude": feature.geometry.coordinates[1],
                 "longitude": feature.geometry.coordinates[0],
                 "address": feature.properties.iconCaption,
                 "preset": feature.options.preset
-            });
+            })
         })
         .collect::<Vec<serde_json::Value>>();
 
@@ -0,0 +1,165 @@
+use crate::db::DB;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+
+/// History entry of a mass send
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Broadcast {
+    pub id: Thing,
+    /// Content route, or a description of the copied message
+    pub content: String,
+    pub sender: i64,
+    pub audience: String,
+    pub recipients: usize,
+    #[serde(default)]
+    pub sent: usize,
+    #[serde(default)]
+    pub failed: usize,
+    #[serde(default)]
+    pub blacklisted: usize,
+    pub started_at: DateTime<Utc>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateBroadcast {
+    content: String,
+    sender: i64,
+    audience: String,
+    recipients: usize,
+    sent: usize,
+    failed: usize,
+    blacklisted: usize,
+    started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BroadcastClick {
+    pub id: Thing,
+    pub broadcast: String,
+    pub data: String,
+    pub user_id: i64,
+    pub clicked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateBroadcastClick {
+    broadcast: String,
+    data: String,
+    user_id: i64,
+    clicked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClickCount {
+    pub data: String,
+    pub count: i64,
+}
+
+impl Broadcast {
+    pub fn record_id(&self) -> String {
+        self.id.id.to_string()
+    }
+}
+
+/// Record the start of a broadcast; `record_id` links it to its broadcast job
+pub async fn start_broadcast(
+    record_id: Option<&str>,
+    content: &str,
+    sender: i64,
+    audience: &str,
+    recipients: usize,
+) -> Result<Broadcast> {
+    let broadcast = CreateBroadcast {
+        content: content.to_string(),
+        sender,
+        audience: audience.to_string(),
+        recipients,
+        sent: 0,
+        failed: 0,
+        blacklisted: 0,
+        started_at: Utc::now(),
+    };
+
+    let created: Option<Broadcast> = match record_id {
+        Some(record_id) => DB.create(("broadcast", record_id)).content(broadcast).await,
+        None => DB.create("broadcast").content(broadcast).await,
+    }
+    .map_err(|e| anyhow!("Failed to create broadcast: {}", e))?;
+
+    created.ok_or_else(|| anyhow!("Broadcast was not created"))
+}
+
+/// Get a history entry by its record ID, the one of its job for queued broadcasts
+pub async fn get_broadcast(record_id: &str) -> Result<Option<Broadcast>> {
+    let broadcast: Option<Broadcast> = DB
+        .select(("broadcast", record_id))
+        .await
+        .map_err(|e| anyhow!("Failed to query broadcast: {}", e))?;
+    Ok(broadcast)
+}
+
+/// Store the delivery totals of a broadcast that stopped
+pub async fn finish_broadcast(
+    record_id: &str,
+    sent: usize,
+    failed: usize,
+    blacklisted: usize,
+) -> Result<()> {
+    let _: Option<Broadcast> = DB
+        .update(("broadcast", record_id))
+        .merge(serde_json::json!({
+            "sent": sent,
+            "failed": failed,
+            "blacklisted": blacklisted,
+            "finished_at": Utc::now()
+        }))
+        .await
+        .map_err(|e| anyhow!("Failed to update broadcast: {}", e))?;
+    Ok(())
+}
+
+/// Get the latest broadcasts, newest first
+pub async fn get_recent_broadcasts(limit: usize) -> Result<Vec<Broadcast>> {
+    let broadcasts: Vec<Broadcast> = DB
+        .query("SELECT * FROM broadcast ORDER BY started_at DESC LIMIT $limit")
+        .bind(("limit", limit))
+        .await
+        .map_err(|e| anyhow!("Failed to query broadcasts: {}", e))?
+        .take(0)?;
+
+    Ok(broadcasts)
+}
+
+/// Record a press on an inline button of a broadcast message
+pub async fn record_broadcast_click(record_id: &str, data: &str, user_id: i64) -> Result<()> {
+    let _: Option<BroadcastClick> = DB
+        .create("broadcast_click")
+        .content(CreateBroadcastClick {
+            broadcast: record_id.to_string(),
+            data: data.to_string(),
+            user_id,
+            clicked_at: Utc::now(),
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to record broadcast click: {}", e))?;
+    Ok(())
+}
+
+/// Button presses of a broadcast, grouped by callback data
+pub async fn get_broadcast_clicks(record_id: &str) -> Result<Vec<ClickCount>> {
+    let clicks: Vec<ClickCount> = DB
+        .query(
+            "SELECT data, count() AS count FROM broadcast_click \
+             WHERE broadcast = $broadcast GROUP BY data",
+        )
+        .bind(("broadcast", record_id.to_string()))
+        .await
+        .map_err(|e| anyhow!("Failed to query broadcast clicks: {}", e))?
+        .take(0)?;
+
+    Ok(clicks)
+}
@@ -0,0 +1,414 @@
+use crate::db::DB;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for the admin to confirm the preview, scheduled jobs included
+    Draft,
+    /// Waiting for `scheduled_at`, recipients are selected when it comes
+    Scheduled,
+    Pending,
+    Running,
+    Paused,
+    Cancelled,
+    Completed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipientStatus {
+    /// Claimed by the worker, the send may or may not have reached Telegram
+    Sending,
+    Sent,
+    Failed,
+    Blacklisted,
+    /// The worker stopped while sending; not retried to avoid a double send
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UrlButton {
+    pub text: String,
+    pub url: String,
+}
+
+/// Admin-composed message broadcast with `copy_message` instead of a content route
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CopiedMessage {
+    pub chat_id: i64,
+    pub message_id: i32,
+    #[serde(default)]
+    pub buttons: Vec<UrlButton>,
+}
+
+/// Campaign day sent to the subscribers instead of a content route: the caption goes under
+/// the photo, or alone when the day has none
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CampaignPost {
+    pub campaign: String,
+    pub season: String,
+    pub day: u32,
+    pub photo: Option<String>,
+    pub caption: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BroadcastJob {
+    pub id: Thing,
+    pub route: String,
+    pub audience: String,
+    pub admin_chat_id: i64,
+    pub status: JobStatus,
+    /// Recipients in sending order, fixed when the job is created
+    pub recipients: Vec<i64>,
+    /// Index of the next recipient to send to
+    pub cursor: usize,
+    /// Status of every recipient that was reached by the cursor, keyed by user ID
+    #[serde(default)]
+    pub statuses: HashMap<String, RecipientStatus>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Admin chat message showing the progress of the job
+    #[serde(default)]
+    pub status_message_id: Option<i32>,
+    #[serde(default)]
+    pub scheduled_at: Option<DateTime<Utc>>,
+    /// Message to copy to the recipients, `route` is ignored when set
+    #[serde(default)]
+    pub message: Option<CopiedMessage>,
+    /// Campaign day to send, `route` is ignored when set
+    #[serde(default)]
+    pub post: Option<CampaignPost>,
+    /// URL buttons of the content when the job started, counted buttons refer to them by position
+    #[serde(default)]
+    pub links: Vec<UrlButton>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateBroadcastJob {
+    route: String,
+    audience: String,
+    admin_chat_id: i64,
+    status: JobStatus,
+    recipients: Vec<i64>,
+    cursor: usize,
+    statuses: HashMap<String, RecipientStatus>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    scheduled_at: Option<DateTime<Utc>>,
+    message: Option<CopiedMessage>,
+    post: Option<CampaignPost>,
+}
+
+impl BroadcastJob {
+    pub fn record_id(&self) -> String {
+        self.id.id.to_string()
+    }
+
+    /// Short description of what the job sends
+    pub fn content_label(&self) -> String {
+        match (&self.message, &self.post) {
+            (Some(message), _) => format!("сообщение {}", message.message_id),
+            (None, Some(post)) => format!("{} (день {})", self.route, post.day),
+            (None, None) => self.route.clone(),
+        }
+    }
+
+    /// Number of recipients with the given status
+    pub fn count(&self, status: RecipientStatus) -> usize {
+        self.statuses.values().filter(|s| **s == status).count()
+    }
+
+    /// Number of recipients the cursor has not reached yet
+    pub fn remaining(&self) -> usize {
+        self.recipients.len().saturating_sub(self.cursor)
+    }
+}
+
+async fn insert_broadcast_job(job: CreateBroadcastJob) -> Result<BroadcastJob> {
+    let created: Option<BroadcastJob> = DB
+        .create("broadcast_job")
+        .content(job)
+        .await
+        .map_err(|e| anyhow!("Failed to create broadcast job: {}", e))?;
+
+    created.ok_or_else(|| anyhow!("Broadcast job was not created"))
+}
+
+/// Create a broadcast job for a fixed list of recipients, started once the admin confirms it
+pub async fn create_broadcast_job(
+    route: &str,
+    message: Option<CopiedMessage>,
+    audience: &str,
+    admin_chat_id: i64,
+    recipients: Vec<i64>,
+) -> Result<BroadcastJob> {
+    let now = Utc::now();
+    let created = insert_broadcast_job(CreateBroadcastJob {
+        route: route.to_string(),
+        audience: audience.to_string(),
+        admin_chat_id,
+        status: JobStatus::Draft,
+        recipients,
+        cursor: 0,
+        statuses: HashMap::new(),
+        created_at: now,
+        updated_at: now,
+        scheduled_at: None,
+        message,
+        post: None,
+    })
+    .await?;
+
+    log::info!(
+        "Broadcast job {} created for route {} ({} recipients)",
+        created.id,
+        route,
+        created.recipients.len()
+    );
+    Ok(created)
+}
+
+/// Create a broadcast job that starts at the given moment once the admin confirms it
+pub async fn create_scheduled_broadcast_job(
+    route: &str,
+    audience: &str,
+    admin_chat_id: i64,
+    scheduled_at: DateTime<Utc>,
+) -> Result<BroadcastJob> {
+    let now = Utc::now();
+    let created = insert_broadcast_job(CreateBroadcastJob {
+        route: route.to_string(),
+        audience: audience.to_string(),
+        admin_chat_id,
+        status: JobStatus::Draft,
+        recipients: vec![],
+        cursor: 0,
+        statuses: HashMap::new(),
+        created_at: now,
+        updated_at: now,
+        scheduled_at: Some(scheduled_at),
+        message: None,
+        post: None,
+    })
+    .await?;
+
+    log::info!(
+        "Broadcast job {} scheduled for route {} at {}",
+        created.id,
+        route,
+        scheduled_at
+    );
+    Ok(created)
+}
+
+/// Create a job sending a campaign day to its subscribers, queued right away since the day
+/// is either confirmed by the admin or due
+pub async fn create_campaign_broadcast_job(
+    route: &str,
+    post: CampaignPost,
+    audience: &str,
+    admin_chat_id: i64,
+    recipients: Vec<i64>,
+) -> Result<BroadcastJob> {
+    let now = Utc::now();
+    let created = insert_broadcast_job(CreateBroadcastJob {
+        route: route.to_string(),
+        audience: audience.to_string(),
+        admin_chat_id,
+        status: JobStatus::Pending,
+        recipients,
+        cursor: 0,
+        statuses: HashMap::new(),
+        created_at: now,
+        updated_at: now,
+        scheduled_at: None,
+        message: None,
+        post: Some(post),
+    })
+    .await?;
+
+    log::info!(
+        "Broadcast job {} created for campaign day {} ({} recipients)",
+        created.id,
+        route,
+        created.recipients.len()
+    );
+    Ok(created)
+}
+
+/// Get the jobs that were created for a campaign day, whatever their status
+pub async fn get_campaign_broadcast_jobs(
+    campaign: &str,
+    season: &str,
+    day: u32,
+) -> Result<Vec<BroadcastJob>> {
+    let jobs: Vec<BroadcastJob> = DB
+        .query(
+            "SELECT * FROM broadcast_job WHERE post.campaign = $campaign \
+             AND post.season = $season AND post.day = $day",
+        )
+        .bind(("campaign", campaign.to_string()))
+        .bind(("season", season.to_string()))
+        .bind(("day", day))
+        .await
+        .map_err(|e| anyhow!("Failed to query broadcast jobs: {}", e))?
+        .take(0)?;
+
+    Ok(jobs)
+}
+
+/// Get scheduled jobs, soonest first
+pub async fn get_scheduled_broadcast_jobs() -> Result<Vec<BroadcastJob>> {
+    let jobs: Vec<BroadcastJob> = DB
+        .query("SELECT * FROM broadcast_job WHERE status = 'scheduled' ORDER BY scheduled_at ASC")
+        .await
+        .map_err(|e| anyhow!("Failed to query broadcast jobs: {}", e))?
+        .take(0)?;
+
+    Ok(jobs)
+}
+
+/// Fill in the recipients of a scheduled job and hand it over to the broadcast worker.
+/// `false` when the admin cancelled it in the meantime
+pub async fn start_scheduled_broadcast_job(
+    job: &BroadcastJob,
+    recipients: Vec<i64>,
+) -> Result<bool> {
+    merge_broadcast_job_from(
+        job,
+        JobStatus::Scheduled,
+        serde_json::json!({
+            "status": JobStatus::Pending,
+            "recipients": recipients,
+            "updated_at": Utc::now()
+        }),
+    )
+    .await
+}
+
+/// Get jobs that are still pending or were interrupted while running, oldest first
+pub async fn get_unfinished_broadcast_jobs() -> Result<Vec<BroadcastJob>> {
+    let jobs: Vec<BroadcastJob> = DB
+        .query(
+            "SELECT * FROM broadcast_job WHERE status IN ['pending', 'running'] \
+             ORDER BY created_at ASC",
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to query broadcast jobs: {}", e))?
+        .take(0)?;
+
+    Ok(jobs)
+}
+
+/// Merge changes into a job without reading it back: the record holds every recipient and
+/// status, too much to transfer after each batch
+async fn merge_broadcast_job(job: &BroadcastJob, changes: serde_json::Value) -> Result<()> {
+    DB.query("UPDATE type::thing('broadcast_job', $id) MERGE $changes RETURN NONE")
+        .bind(("id", job.record_id()))
+        .bind(("changes", changes))
+        .await
+        .map_err(|e| anyhow!("Failed to update broadcast job: {}", e))?
+        .check()
+        .map_err(|e| anyhow!("Failed to update broadcast job: {}", e))?;
+    Ok(())
+}
+
+/// Merge changes into a job only while it has the given status, telling whether it had
+async fn merge_broadcast_job_from(
+    job: &BroadcastJob,
+    from: JobStatus,
+    changes: serde_json::Value,
+) -> Result<bool> {
+    let updated: Vec<JobStatus> = DB
+        .query(
+            "UPDATE type::thing('broadcast_job', $id) MERGE $changes \
+             WHERE status = $from RETURN VALUE status",
+        )
+        .bind(("id", job.record_id()))
+        .bind(("changes", changes))
+        .bind(("from", from))
+        .await
+        .map_err(|e| anyhow!("Failed to update broadcast job: {}", e))?
+        .take(0)?;
+    Ok(!updated.is_empty())
+}
+
+/// Move a pending job to running for the worker, with the links it sends. `false` when the
+/// admin paused or cancelled it in the meantime
+pub async fn claim_broadcast_job(job: &BroadcastJob, links: &[UrlButton]) -> Result<bool> {
+    merge_broadcast_job_from(
+        job,
+        JobStatus::Pending,
+        serde_json::json!({
+            "status": JobStatus::Running,
+            "links": links,
+            "updated_at": Utc::now()
+        }),
+    )
+    .await
+}
+
+/// Get a job by its record ID
+pub async fn get_broadcast_job(record_id: &str) -> Result<Option<BroadcastJob>> {
+    let job: Option<BroadcastJob> = DB
+        .select(("broadcast_job", record_id))
+        .await
+        .map_err(|e| anyhow!("Failed to query broadcast job: {}", e))?;
+    Ok(job)
+}
+
+/// Remember the admin chat message that shows the progress of a job
+pub async fn set_broadcast_job_status_message(job: &BroadcastJob, message_id: i32) -> Result<()> {
+    merge_broadcast_job(
+        job,
+        serde_json::json!({
+            "status_message_id": message_id,
+            "updated_at": Utc::now()
+        }),
+    )
+    .await
+}
+
+/// Change the status of a job
+pub async fn update_broadcast_job_status(job: &BroadcastJob, status: JobStatus) -> Result<()> {
+    let now = Utc::now();
+    let mut changes = serde_json::json!({
+        "status": status,
+        "updated_at": now,
+    });
+    if matches!(status, JobStatus::Completed | JobStatus::Cancelled) {
+        changes["finished_at"] = serde_json::json!(now);
+    }
+
+    merge_broadcast_job(job, changes).await
+}
+
+/// Store recipient statuses and move the cursor of a job
+pub async fn update_broadcast_job_progress(
+    job: &BroadcastJob,
+    cursor: usize,
+    statuses: &[(i64, RecipientStatus)],
+) -> Result<()> {
+    let statuses: serde_json::Map<String, serde_json::Value> = statuses
+        .iter()
+        .map(|(user_id, status)| (user_id.to_string(), serde_json::json!(status)))
+        .collect();
+
+    merge_broadcast_job(
+        job,
+        serde_json::json!({
+            "cursor": cursor,
+            "statuses": statuses,
+            "updated_at": Utc::now()
+        }),
+    )
+    .await
+}
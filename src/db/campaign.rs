@@ -0,0 +1,109 @@
+use crate::db::DB;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Campaign day already delivered to the subscribers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CampaignDelivery {
+    pub campaign: String,
+    pub season: String,
+    pub day: u32,
+    pub delivered_at: DateTime<Utc>,
+}
+
+/// Days of a campaign season that were already delivered
+pub async fn get_delivered_campaign_days(campaign: &str, season: &str) -> Result<Vec<u32>> {
+    let days: Vec<u32> = DB
+        .query(
+            "SELECT VALUE day FROM campaign_delivery \
+             WHERE campaign = $campaign AND season = $season",
+        )
+        .bind(("campaign", campaign.to_string()))
+        .bind(("season", season.to_string()))
+        .await
+        .map_err(|e| anyhow!("Failed to query campaign deliveries: {}", e))?
+        .take(0)?;
+
+    Ok(days)
+}
+
+/// Remember that a day was delivered, so it is not sent again
+pub async fn mark_campaign_day_delivered(campaign: &str, season: &str, day: u32) -> Result<()> {
+    let _: Option<CampaignDelivery> = DB
+        .upsert((
+            "campaign_delivery",
+            format!("{}_{}_{}", campaign, season, day),
+        ))
+        .content(CampaignDelivery {
+            campaign: campaign.to_string(),
+            season: season.to_string(),
+            day,
+            delivered_at: Utc::now(),
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to record campaign delivery: {}", e))?;
+    Ok(())
+}
+
+/// Prize draw kept for auditing: the same seed and participants give the same winners
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CampaignDraw {
+    pub campaign: String,
+    pub season: String,
+    #[serde(with = "seed_as_text")]
+    pub seed: u64,
+    pub min_tasks: usize,
+    pub count: usize,
+    pub participants: Vec<i64>,
+    pub winners: Vec<i64>,
+    pub drawn_at: DateTime<Utc>,
+}
+
+pub async fn create_campaign_draw(draw: CampaignDraw) -> Result<()> {
+    let _: Option<CampaignDraw> = DB
+        .create("campaign_draw")
+        .content(draw)
+        .await
+        .map_err(|e| anyhow!("Failed to record campaign draw: {}", e))?;
+    Ok(())
+}
+
+/// SurrealDB stores integers as i64, so a seed above `i64::MAX` would come back wrapped and no
+/// longer reproduce the draw. Kept as text instead
+mod seed_as_text {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&seed.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_seed_round_trip() {
+        let draw = CampaignDraw {
+            campaign: "advent".to_string(),
+            season: "2025".to_string(),
+            seed: u64::MAX - 1,
+            min_tasks: 10,
+            count: 3,
+            participants: vec![1, 2, 3],
+            winners: vec![2],
+            drawn_at: Utc::now(),
+        };
+
+        let value = surrealdb::value::to_value(draw).unwrap();
+        let stored: CampaignDraw = surrealdb::value::from_value(value).unwrap();
+        assert_eq!(stored.seed, u64::MAX - 1);
+    }
+}
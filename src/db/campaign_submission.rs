@@ -0,0 +1,124 @@
+use crate::db::DB;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmissionStatus {
+    /// Waiting for the admin review
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// Participant's report on a campaign task
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CampaignSubmission {
+    pub id: Thing,
+    pub campaign: String,
+    pub season: String,
+    pub day: u32,
+    pub user_id: i64,
+    /// Text or caption of the report, links included
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Largest size of the attached photo
+    #[serde(default)]
+    pub photo_file_id: Option<String>,
+    /// Report message in the user's chat, copied to the admin for review
+    pub message_id: i32,
+    pub status: SubmissionStatus,
+    pub submitted_at: DateTime<Utc>,
+    #[serde(default)]
+    pub reviewed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateCampaignSubmission {
+    campaign: String,
+    season: String,
+    day: u32,
+    user_id: i64,
+    text: Option<String>,
+    photo_file_id: Option<String>,
+    message_id: i32,
+    status: SubmissionStatus,
+    submitted_at: DateTime<Utc>,
+}
+
+impl CampaignSubmission {
+    pub fn record_id(&self) -> String {
+        self.id.id.to_string()
+    }
+}
+
+/// Store a report awaiting the admin review
+pub async fn create_campaign_submission(
+    campaign: &str,
+    season: &str,
+    day: u32,
+    user_id: i64,
+    text: Option<String>,
+    photo_file_id: Option<String>,
+    message_id: i32,
+) -> Result<CampaignSubmission> {
+    let created: Option<CampaignSubmission> = DB
+        .create("campaign_submission")
+        .content(CreateCampaignSubmission {
+            campaign: campaign.to_string(),
+            season: season.to_string(),
+            day,
+            user_id,
+            text,
+            photo_file_id,
+            message_id,
+            status: SubmissionStatus::Pending,
+            submitted_at: Utc::now(),
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to create campaign submission: {}", e))?;
+
+    created.ok_or_else(|| anyhow!("Campaign submission was not created"))
+}
+
+pub async fn get_campaign_submission(record_id: &str) -> Result<Option<CampaignSubmission>> {
+    let submission: Option<CampaignSubmission> = DB
+        .select(("campaign_submission", record_id))
+        .await
+        .map_err(|e| anyhow!("Failed to query campaign submission: {}", e))?;
+    Ok(submission)
+}
+
+/// All reports of a campaign season, for the leaderboard
+pub async fn get_campaign_submissions(
+    campaign: &str,
+    season: &str,
+) -> Result<Vec<CampaignSubmission>> {
+    let submissions: Vec<CampaignSubmission> = DB
+        .query(
+            "SELECT * FROM campaign_submission \
+             WHERE campaign = $campaign AND season = $season",
+        )
+        .bind(("campaign", campaign.to_string()))
+        .bind(("season", season.to_string()))
+        .await
+        .map_err(|e| anyhow!("Failed to query campaign submissions: {}", e))?
+        .take(0)?;
+
+    Ok(submissions)
+}
+
+/// Store the admin's decision on a report
+pub async fn review_campaign_submission(record_id: &str, status: SubmissionStatus) -> Result<()> {
+    let _: Option<CampaignSubmission> = DB
+        .update(("campaign_submission", record_id))
+        .merge(serde_json::json!({
+            "status": status,
+            "reviewed_at": Utc::now(),
+        }))
+        .await
+        .map_err(|e| anyhow!("Failed to review campaign submission: {}", e))?;
+    Ok(())
+}
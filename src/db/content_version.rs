@@ -0,0 +1,119 @@
+use crate::db::DB;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use surrealdb::sql::Thing;
+
+/// Source of a content page edited from Telegram
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentVersion {
+    pub id: Thing,
+    pub route: String,
+    /// Markdown with front-matter, `None` for going back to the file of the page
+    #[serde(default)]
+    pub source: Option<String>,
+    pub author: i64,
+    pub created_at: DateTime<Utc>,
+    /// Unset while the version is a draft awaiting publication after its preview
+    #[serde(default)]
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateContentVersion {
+    route: String,
+    source: Option<String>,
+    author: i64,
+    created_at: DateTime<Utc>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+impl ContentVersion {
+    pub fn record_id(&self) -> String {
+        self.id.id.to_string()
+    }
+}
+
+/// Store a version of a page, as a draft or already published
+pub async fn create_content_version(
+    route: &str,
+    source: Option<String>,
+    author: i64,
+    published: bool,
+) -> Result<ContentVersion> {
+    let now = Utc::now();
+    let created: Option<ContentVersion> = DB
+        .create("content_version")
+        .content(CreateContentVersion {
+            route: route.to_string(),
+            source,
+            author,
+            created_at: now,
+            published_at: published.then_some(now),
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to create content version: {}", e))?;
+
+    created.ok_or_else(|| anyhow!("Content version was not created"))
+}
+
+pub async fn get_content_version(record_id: &str) -> Result<Option<ContentVersion>> {
+    let version: Option<ContentVersion> = DB
+        .select(("content_version", record_id))
+        .await
+        .map_err(|e| anyhow!("Failed to query content version: {}", e))?;
+    Ok(version)
+}
+
+pub async fn publish_content_version(record_id: &str) -> Result<()> {
+    let _: Option<ContentVersion> = DB
+        .update(("content_version", record_id))
+        .merge(serde_json::json!({ "published_at": Utc::now() }))
+        .await
+        .map_err(|e| anyhow!("Failed to publish content version: {}", e))?;
+    Ok(())
+}
+
+pub async fn delete_content_version(record_id: &str) -> Result<()> {
+    let _: Option<ContentVersion> = DB
+        .delete(("content_version", record_id))
+        .await
+        .map_err(|e| anyhow!("Failed to delete content version: {}", e))?;
+    Ok(())
+}
+
+/// Published versions of a page, latest first
+pub async fn get_content_versions(route: &str, limit: usize) -> Result<Vec<ContentVersion>> {
+    let versions: Vec<ContentVersion> = DB
+        .query(
+            "SELECT * FROM content_version \
+             WHERE route = $route AND published_at != NONE \
+             ORDER BY published_at DESC LIMIT $limit",
+        )
+        .bind(("route", route.to_string()))
+        .bind(("limit", limit))
+        .await
+        .map_err(|e| anyhow!("Failed to query content versions: {}", e))?
+        .take(0)?;
+
+    Ok(versions)
+}
+
+/// Latest published version of every edited page
+pub async fn get_live_content_versions() -> Result<Vec<ContentVersion>> {
+    let versions: Vec<ContentVersion> = DB
+        .query(
+            "SELECT * FROM content_version \
+             WHERE published_at != NONE ORDER BY published_at DESC",
+        )
+        .await
+        .map_err(|e| anyhow!("Failed to query content versions: {}", e))?
+        .take(0)?;
+
+    let mut seen = HashSet::new();
+    Ok(versions
+        .into_iter()
+        .filter(|version| seen.insert(version.route.clone()))
+        .collect())
+}
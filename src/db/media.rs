@@ -0,0 +1,42 @@
+use crate::db::DB;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Telegram `file_id` of an uploaded embedded file, keyed by the hash of its content
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MediaFile {
+    pub name: String,
+    pub file_id: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+pub async fn get_media_file_id(hash: &str) -> Result<Option<String>> {
+    let media: Option<MediaFile> = DB
+        .select(("media_file", hash))
+        .await
+        .map_err(|e| anyhow!("Failed to query media file: {}", e))?;
+    Ok(media.map(|media| media.file_id))
+}
+
+pub async fn set_media_file_id(hash: &str, name: &str, file_id: &str) -> Result<()> {
+    let _: Option<MediaFile> = DB
+        .upsert(("media_file", hash))
+        .content(MediaFile {
+            name: name.to_string(),
+            file_id: file_id.to_string(),
+            uploaded_at: Utc::now(),
+        })
+        .await
+        .map_err(|e| anyhow!("Failed to store media file: {}", e))?;
+    Ok(())
+}
+
+/// Forget a `file_id` Telegram no longer accepts, so the file is uploaded again
+pub async fn delete_media_file_id(hash: &str) -> Result<()> {
+    let _: Option<MediaFile> = DB
+        .delete(("media_file", hash))
+        .await
+        .map_err(|e| anyhow!("Failed to delete media file: {}", e))?;
+    Ok(())
+}
@@ -1,4 +1,10 @@
 pub use bin_location::*;
+pub use broadcast::*;
+pub use broadcast_job::*;
+pub use campaign::*;
+pub use campaign_submission::*;
+pub use content_version::*;
+pub use media::*;
 use once_cell::sync::Lazy;
 use std::env;
 use surrealdb::{
@@ -9,6 +15,12 @@ use surrealdb::{
 pub use user::*;
 
 mod bin_location;
+mod broadcast;
+mod broadcast_job;
+mod campaign;
+mod campaign_submission;
+mod content_version;
+mod media;
 mod user;
 
 pub static DB: Lazy<Surreal<Client>> = Lazy::new(Surreal::init);
@@ -21,7 +33,7 @@ pub async fn connect_db() -> surrealdb::Result<()> {
     let namespace = env::var("NAMESPACE").expect("NAMESPACE must be set in environment");
     let dbname = env::var("DBNAME").expect("DBNAME must be set in environment");
 
-    let _ = DB.connect::<Ws>(&format!("{url}:{port}")).await?;
+    DB.connect::<Ws>(&format!("{url}:{port}")).await?;
 
     let _ = DB
         .signin(Root {
@@ -30,7 +42,7 @@ pub async fn connect_db() -> surrealdb::Result<()> {
         })
         .await?;
 
-    let _ = DB.use_ns(&namespace).use_db(&dbname).await?;
+    DB.use_ns(&namespace).use_db(&dbname).await?;
 
     Ok(())
 }
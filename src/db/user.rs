@@ -15,6 +15,25 @@ pub struct User {
     pub updated_at: DateTime<Utc>,
     #[serde(default)]
     pub blacklisted: bool,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub language_code: Option<String>,
+    #[serde(default)]
+    pub is_premium: bool,
+    #[serde(default)]
+    pub last_seen_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub message_count: i64,
+    #[serde(default)]
+    pub callback_count: i64,
+    /// Last location the user sent to the bot
+    #[serde(default)]
+    pub home_latitude: Option<f64>,
+    #[serde(default)]
+    pub home_longitude: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,6 +43,17 @@ struct CreateUser {
     subscriptions: Vec<String>,
     updated_at: DateTime<Utc>,
     blacklisted: bool,
+    message_count: i64,
+    callback_count: i64,
+}
+
+/// Telegram profile data copied onto the user record
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub first_name: String,
+    pub username: Option<String>,
+    pub language_code: Option<String>,
+    pub is_premium: bool,
 }
 
 /// Store a user ID in the database
@@ -41,6 +71,8 @@ pub async fn store_user(user_id: i64) -> Result<bool> {
         subscriptions: vec![],
         updated_at: now,
         blacklisted: false,
+        message_count: 0,
+        callback_count: 0,
     };
 
     let created: Option<User> = DB
@@ -59,6 +91,16 @@ pub async fn is_user_stored(user_id: i64) -> Result<bool> {
     Ok(users.contains(&user_id))
 }
 
+/// Get all stored user records
+pub async fn get_user_records() -> Result<Vec<User>> {
+    let users: Vec<User> = DB
+        .select("user")
+        .await
+        .map_err(|e| anyhow!("Failed to query users: {}", e))?;
+
+    Ok(users)
+}
+
 /// Get all stored user IDs
 pub async fn get_all_users() -> Result<Vec<i64>> {
     let users: Vec<User> = DB
@@ -296,6 +338,25 @@ struct UserIdRow {
     user_id: i64,
 }
 
+/// Remove a user from the blacklist (the user can receive messages again)
+pub async fn unblacklist_user(user_id: i64) -> Result<bool> {
+    let rows: Vec<UserIdRow> = DB
+        .query(
+            "UPDATE user SET blacklisted = false, updated_at = $now \
+             WHERE user_id = $user_id AND blacklisted = true RETURN user_id",
+        )
+        .bind(("user_id", user_id))
+        .bind(("now", Utc::now()))
+        .await
+        .map_err(|e| anyhow!("Failed to unblacklist user: {}", e))?
+        .take(0)?;
+
+    if !rows.is_empty() {
+        log::info!("User {} removed from blacklist", user_id);
+    }
+    Ok(!rows.is_empty())
+}
+
 pub async fn get_active_users() -> Result<Vec<i64>> {
     let rows: Vec<UserIdRow> = DB
         .query("SELECT user_id FROM user WHERE blacklisted = false")
@@ -305,3 +366,88 @@ pub async fn get_active_users() -> Result<Vec<i64>> {
 
     Ok(rows.into_iter().map(|row| row.user_id).collect())
 }
+
+/// Update profile data and activity counters of a user
+pub async fn update_user_activity(
+    user_id: i64,
+    profile: UserProfile,
+    messages: i64,
+    callbacks: i64,
+) -> Result<()> {
+    let sql = r#"
+    UPDATE user SET
+        first_name = $first_name,
+        username = $username,
+        language_code = $language_code,
+        is_premium = $is_premium,
+        last_seen_at = $now,
+        message_count = (message_count ?? 0) + $messages,
+        callback_count = (callback_count ?? 0) + $callbacks,
+        updated_at = $now
+    WHERE user_id = $user_id;
+    "#;
+
+    DB.query(sql)
+        .bind(("user_id", user_id))
+        .bind(("first_name", profile.first_name))
+        .bind(("username", profile.username))
+        .bind(("language_code", profile.language_code))
+        .bind(("is_premium", profile.is_premium))
+        .bind(("messages", messages))
+        .bind(("callbacks", callbacks))
+        .bind(("now", Utc::now()))
+        .await
+        .map_err(|e| anyhow!("Failed to update user activity: {}", e))?
+        .check()
+        .map_err(|e| anyhow!("Failed to update user activity: {}", e))?;
+
+    Ok(())
+}
+
+/// Find a user by Telegram username, with or without the leading `@`, ignoring case
+pub async fn get_user_by_username(username: &str) -> Result<Option<User>> {
+    let username = username.trim().trim_start_matches('@').to_lowercase();
+    let users: Vec<User> = DB
+        .query("SELECT * FROM user WHERE string::lowercase(username ?? '') = $username LIMIT 1")
+        .bind(("username", username))
+        .await
+        .map_err(|e| anyhow!("Failed to query users: {}", e))?
+        .take(0)?;
+
+    Ok(users.into_iter().next())
+}
+
+#[derive(serde::Deserialize)]
+struct CountRow {
+    count: i64,
+}
+
+/// Count users seen at or after the given moment
+pub async fn count_users_seen_since(since: DateTime<Utc>) -> Result<i64> {
+    let rows: Vec<CountRow> = DB
+        .query("SELECT count() AS count FROM user WHERE last_seen_at >= $since GROUP ALL")
+        .bind(("since", since))
+        .await
+        .map_err(|e| anyhow!("Failed to count users: {}", e))?
+        .take(0)?;
+
+    Ok(rows.first().map(|row| row.count).unwrap_or(0))
+}
+
+/// Remember the last location a user sent as their home location
+pub async fn set_user_home_location(user_id: i64, latitude: f64, longitude: f64) -> Result<()> {
+    DB.query(
+        "UPDATE user SET home_latitude = $latitude, home_longitude = $longitude, \
+         updated_at = $now WHERE user_id = $user_id",
+    )
+    .bind(("user_id", user_id))
+    .bind(("latitude", latitude))
+    .bind(("longitude", longitude))
+    .bind(("now", Utc::now()))
+    .await
+    .map_err(|e| anyhow!("Failed to update user location: {}", e))?
+    .check()
+    .map_err(|e| anyhow!("Failed to update user location: {}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::warn;
+use once_cell::sync::Lazy;
+use teloxide::{ApiError, RequestError};
+use tokio::time::Instant;
+
+/// Number of attempts made for a send that keeps failing with transient errors
+pub const MAX_ATTEMPTS: u32 = 4;
+/// Delay before the first retry, doubled on every following one
+pub const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Telegram's global limit for bulk notifications, in messages per second
+pub const MAX_RATE: f64 = 30.0;
+/// Lowest rate the limiter slows down to after repeated 429 responses
+pub const MIN_RATE: f64 = 1.0;
+/// Rate regained after every successful send
+pub const RATE_RECOVERY: f64 = 0.1;
+/// Minimal interval between two messages to the same chat
+pub const PER_CHAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Limiter shared by every bulk sender of the bot
+pub static LIMITER: Lazy<RateLimiter> = Lazy::new(|| RateLimiter::new(MAX_RATE));
+
+#[derive(Debug)]
+struct LimiterState {
+    rate: f64,
+    next_slot: Instant,
+    paused_until: Option<Instant>,
+    /// Number of 429 responses so far, voiding the slots reserved before each of them
+    pauses: u64,
+    chats: HashMap<i64, Instant>,
+}
+
+impl LimiterState {
+    /// Reserve the next free slot for a chat
+    fn reserve(&mut self, chat_id: i64) -> Instant {
+        let now = Instant::now();
+
+        let mut slot = self.next_slot.max(now);
+        if let Some(paused_until) = self.paused_until {
+            slot = slot.max(paused_until);
+        }
+        if let Some(chat_slot) = self.chats.get(&chat_id) {
+            slot = slot.max(*chat_slot);
+        }
+
+        self.next_slot = slot + Duration::from_secs_f64(1.0 / self.rate);
+        self.chats.insert(chat_id, slot + PER_CHAT_INTERVAL);
+        self.chats.retain(|_, chat_slot| *chat_slot > now);
+        slot
+    }
+}
+
+/// Spaces sends out to respect Telegram's global and per-chat limits,
+/// slowing down when Telegram answers with 429 and speeding up again afterwards
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_rate: f64,
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new(max_rate: f64) -> Self {
+        Self {
+            max_rate,
+            state: Mutex::new(LimiterState {
+                rate: max_rate,
+                next_slot: Instant::now(),
+                paused_until: None,
+                pauses: 0,
+                chats: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Current rate in messages per second
+    pub fn rate(&self) -> f64 {
+        self.state.lock().unwrap().rate
+    }
+
+    /// Reserve the next free slot for a chat and wait until it comes
+    pub async fn acquire(&self, chat_id: i64) {
+        let (mut slot, mut pauses) = {
+            let mut state = self.state.lock().unwrap();
+            (state.reserve(chat_id), state.pauses)
+        };
+
+        loop {
+            tokio::time::sleep_until(slot).await;
+
+            // Telegram asked to back off while this sender slept: its slot was reserved before
+            // the pause and at the old rate, so it takes a new one
+            let mut state = self.state.lock().unwrap();
+            if state.pauses == pauses {
+                return;
+            }
+            if state.chats.get(&chat_id) == Some(&(slot + PER_CHAT_INTERVAL)) {
+                state.chats.remove(&chat_id);
+            }
+            slot = state.reserve(chat_id);
+            pauses = state.pauses;
+        }
+    }
+
+    /// Telegram asked to back off: halve the rate and hold every sender for `retry_after`.
+    /// Slots reserved so far are given up, their senders reserve again at the new rate
+    pub fn on_rate_limited(&self, retry_after: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let resume_at = Instant::now() + retry_after;
+        let paused_until = state.paused_until.map_or(resume_at, |p| p.max(resume_at));
+        state.rate = (state.rate / 2.0).max(MIN_RATE);
+        state.paused_until = Some(paused_until);
+        state.next_slot = paused_until;
+        state.pauses += 1;
+        warn!(
+            "Rate limited by Telegram, pausing for {:?} and slowing down to {:.1} msg/s",
+            retry_after, state.rate
+        );
+    }
+
+    /// A send went through: slowly climb back to the maximal rate
+    pub fn on_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = (state.rate + RATE_RECOVERY).min(self.max_rate);
+    }
+}
+
+/// How a failed send should be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The user can't be reached anymore (blocked the bot, deactivated, chat not found)
+    Permanent,
+    /// Temporary failure (network, rate limit), worth retrying
+    Transient { retry_after: Option<Duration> },
+    /// The request itself is broken (e.g. invalid HTML), retrying won't help
+    Invalid,
+}
+
+/// Sort a Telegram request error into permanent, transient and invalid-request failures
+pub fn classify(err: &RequestError) -> ErrorClass {
+    match err {
+        RequestError::Api(api_error) => match api_error {
+            ApiError::BotBlocked
+            | ApiError::UserDeactivated
+            | ApiError::ChatNotFound
+            | ApiError::UserNotFound
+            | ApiError::BotKicked
+            | ApiError::BotKickedFromSupergroup
+            | ApiError::CantInitiateConversation
+            | ApiError::CantTalkWithBots
+            | ApiError::GroupDeactivated => ErrorClass::Permanent,
+            _ => ErrorClass::Invalid,
+        },
+        RequestError::RetryAfter(duration) => ErrorClass::Transient {
+            retry_after: Some(*duration),
+        },
+        RequestError::Network(_) | RequestError::Io(_) | RequestError::InvalidJson { .. } => {
+            ErrorClass::Transient { retry_after: None }
+        }
+        RequestError::MigrateToChatId(_) => ErrorClass::Invalid,
+    }
+}
+
+/// Delay before the given retry (1-based), unless Telegram asked for a specific one
+pub fn backoff_delay(retry: u32, retry_after: Option<Duration>) -> Duration {
+    retry_after.unwrap_or_else(|| BASE_DELAY * 2u32.pow(retry.saturating_sub(1)))
+}
+
+/// Send to a chat through the shared limiter, retrying transient failures
+pub async fn send_with_retry<T, F, Fut>(chat_id: i64, send: F) -> Result<T, RequestError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    send_with_limiter(&LIMITER, chat_id, send).await
+}
+
+/// Run a request once the limiter allows it, retrying transient failures with exponential
+/// backoff and honouring `RetryAfter`
+pub async fn send_with_limiter<T, F, Fut>(
+    limiter: &RateLimiter,
+    chat_id: i64,
+    mut send: F,
+) -> Result<T, RequestError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RequestError>>,
+{
+    let mut attempt = 1;
+    loop {
+        limiter.acquire(chat_id).await;
+        match send().await {
+            Ok(value) => {
+                limiter.on_success();
+                return Ok(value);
+            }
+            Err(err) => match classify(&err) {
+                ErrorClass::Transient {
+                    retry_after: Some(retry_after),
+                } if attempt < MAX_ATTEMPTS => {
+                    limiter.on_rate_limited(retry_after);
+                    attempt += 1;
+                }
+                ErrorClass::Transient { retry_after: None } if attempt < MAX_ATTEMPTS => {
+                    let delay = backoff_delay(attempt, None);
+                    warn!(
+                        "Transient send error (attempt {}), retrying in {:?}: {}",
+                        attempt, delay, err
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return Err(err),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            classify(&RequestError::Api(ApiError::BotBlocked)),
+            ErrorClass::Permanent
+        );
+        assert_eq!(
+            classify(&RequestError::Api(ApiError::UserDeactivated)),
+            ErrorClass::Permanent
+        );
+        assert_eq!(
+            classify(&RequestError::Api(ApiError::CantParseEntities)),
+            ErrorClass::Invalid
+        );
+        assert_eq!(
+            classify(&RequestError::RetryAfter(Duration::from_secs(5))),
+            ErrorClass::Transient {
+                retry_after: Some(Duration::from_secs(5))
+            }
+        );
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(1, None), Duration::from_secs(1));
+        assert_eq!(backoff_delay(3, None), Duration::from_secs(4));
+        assert_eq!(
+            backoff_delay(3, Some(Duration::from_secs(30))),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_with_retry() {
+        let limiter = RateLimiter::new(MAX_RATE);
+        let start = Instant::now();
+        let mut calls = 0;
+        let result = send_with_limiter(&limiter, 1, || {
+            calls += 1;
+            let attempt = calls;
+            async move {
+                if attempt < 3 {
+                    Err(RequestError::RetryAfter(Duration::from_secs(1)))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(limiter.rate() < MAX_RATE);
+
+        let mut calls = 0;
+        let result: Result<(), _> = send_with_limiter(&limiter, 2, || {
+            calls += 1;
+            async { Err(RequestError::Api(ApiError::BotBlocked)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter_global_rate() {
+        let limiter = RateLimiter::new(10.0);
+        let start = Instant::now();
+        for chat_id in 0..21 {
+            limiter.acquire(chat_id).await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter_per_chat_interval() {
+        let limiter = RateLimiter::new(MAX_RATE);
+        let start = Instant::now();
+        limiter.acquire(1).await;
+        limiter.acquire(2).await;
+        assert!(start.elapsed() < PER_CHAT_INTERVAL);
+        limiter.acquire(1).await;
+        assert_eq!(start.elapsed(), PER_CHAT_INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter_pause_reaches_reserved_slots() {
+        let limiter = Arc::new(RateLimiter::new(10.0));
+        let start = Instant::now();
+        limiter.acquire(1).await;
+
+        // Reserved at 0.1s, before Telegram asks to back off
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                limiter.acquire(2).await;
+                Instant::now()
+            })
+        };
+        tokio::task::yield_now().await;
+        limiter.on_rate_limited(Duration::from_secs(5));
+
+        let sent_at = waiter.await.unwrap();
+        assert_eq!(sent_at - start, Duration::from_secs(5));
+
+        // The slots after the pause are spaced at the halved rate
+        limiter.acquire(3).await;
+        assert_eq!(
+            Instant::now() - start,
+            Duration::from_secs(5) + Duration::from_secs_f64(1.0 / 5.0)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter_adapts_to_rate_limits() {
+        let limiter = RateLimiter::new(MAX_RATE);
+        let start = Instant::now();
+        limiter.on_rate_limited(Duration::from_secs(5));
+        assert_eq!(limiter.rate(), MAX_RATE / 2.0);
+
+        limiter.acquire(1).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+        for _ in 0..1000 {
+            limiter.on_success();
+        }
+        assert_eq!(limiter.rate(), MAX_RATE);
+    }
+}
@@ -2,18 +2,22 @@ use std::error::Error;
 
 use log::{error, info};
 use teloxide::{
-    payloads::SendMessageSetters,
+    payloads::{AnswerCallbackQuerySetters, SendMessageSetters},
     prelude::Requester,
-    types::{CallbackQuery, ChatId, Me, Message, ParseMode},
+    types::{CallbackQuery, ChatId, ChatMemberUpdated, Me, Message, ParseMode},
     utils::command::BotCommands,
     Bot,
 };
 
+use crate::callback::{CallbackAction, ConfirmScope};
 use crate::commands::{
-    AdventCommand, BroadcastCommand, ContentCommand, LocationCommand, StopCommand,
+    BroadcastCommand, CampaignCommand, ComposeCommand, ContentCommand, DrawCommand, EditCommand,
+    LocationCommand, ReloadCommand, ScheduleCommand, StatsCommand, StopCommand, SubmissionCommand,
     SubscriptionCommand, ADMIN_ID, TEST_USER_ID,
 };
-use crate::users;
+use crate::db;
+use crate::route::{self, ROOT};
+use crate::users::{self, Activity};
 
 /// These commands are supported:
 #[derive(BotCommands)]
@@ -43,19 +47,60 @@ enum Command {
     /// GiveAway
     GiveAway,
     /// FAQ
-    FAQ,
-    /// Broadcast a message to all users (admin only)
+    Faq,
+    /// Broadcast a message to all users or to an audience (admin only)
     Broadcast,
+    /// Preview the number of users in a broadcast audience (admin only)
+    Audience,
+    /// Compose a message to broadcast (admin only)
+    Compose,
+    /// Broadcast the composed message to all users or to an audience (admin only)
+    SendDraft,
+    /// Schedule a broadcast at a Kaliningrad date and time (admin only)
+    Schedule,
+    /// List scheduled broadcasts (admin only)
+    Scheduled,
+    /// Cancel a scheduled broadcast (admin only)
+    Unschedule,
     /// Send a test message to a specific user
     TestMessage,
-    /// Advent calendar
-    Advent,
-    /// Test advent message to specific user
-    AdventTest,
+    /// Send the current day of a campaign to its subscribers, again with "force" (admin only)
+    Campaign,
+    /// Test the current day of a campaign on a specific user (admin only)
+    CampaignTest,
+    /// Catch up on past campaign days
+    Calendar,
+    /// Participants ranked by approved campaign reports (admin only)
+    Leaderboard,
+    /// Draw prize winners among campaign participants (admin only)
+    Draw,
     /// Stop all subscriptions
     Stop,
+    /// User activity statistics (admin only)
+    Stats,
+    /// Find a user by username (admin only)
+    User,
+    /// Send a message to a user by username (admin only)
+    Message,
+    /// Recent broadcasts with delivery totals and button presses (admin only)
+    Broadcasts,
+    /// Reload content pages from CONTENT_DIR (admin only)
+    Reload,
+    /// Show the source of a content page (admin only)
+    Source,
+    /// Replace the source of a content page (admin only)
+    Edit,
+    /// Published versions of a content page (admin only)
+    Versions,
+    /// Stop waiting for the new source of a content page (admin only)
+    Cancel,
 }
 
+/// Words opening the main menu
+const MENU_WORDS: &[&str] = &["бот", "Бот"];
+/// Words stopping all subscriptions
+const STOP_WORDS: &[&str] = &["стоп", "Стоп", "СТОП"];
+
 fn send_unknown_command_message(text: &str) -> String {
     format!(
         "Неизвестная команда: {}. Попробуйте начать сначала написав \"Бот\"",
@@ -76,10 +121,55 @@ pub async fn message_handler(
                 info!("New user registered from message: {}", user_id);
             }
         }
+        if let Err(e) = users::track_activity(user, Activity::Message).await {
+            error!("Failed to track activity of user {}: {:?}", user_id, e);
+        }
+    }
+
+    // Handle the admin composing a broadcast message
+    if msg.chat.id == ChatId(ADMIN_ID)
+        && ComposeCommand::is_composing()
+        && !msg.text().is_some_and(|text| text.starts_with('/'))
+    {
+        ComposeCommand::handle_message(&bot, &msg).await?;
+        return Ok(());
+    }
+
+    // Handle the new source of a content page the admin is editing
+    if msg.chat.id == ChatId(ADMIN_ID)
+        && EditCommand::is_awaiting(msg.chat.id.0)
+        && !msg.text().is_some_and(|text| text.starts_with('/'))
+    {
+        EditCommand::handle_message(&bot, &msg).await?;
+        return Ok(());
+    }
+
+    // Handle a campaign report the user was asked for. Locations, commands, menu words and
+    // keywords keep working, and the last three mean the user moved on
+    if SubmissionCommand::is_awaiting(msg.chat.id.0) && msg.location().is_none() {
+        let moved_on = msg.text().is_some_and(|text| {
+            text.starts_with('/')
+                || MENU_WORDS.contains(&text)
+                || STOP_WORDS.contains(&text)
+                || route::find_by_keyword(text).is_some()
+        });
+        if !moved_on {
+            SubmissionCommand::handle_message(&bot, &msg).await?;
+            return Ok(());
+        }
+        SubmissionCommand::forget(msg.chat.id.0);
     }
 
     // Handle location message
     if let Some(location) = msg.location() {
+        if let Some(user) = msg.from() {
+            let user_id: i64 = user.id.0.try_into().unwrap();
+            if let Err(e) =
+                users::set_home_location(user_id, location.latitude, location.longitude).await
+            {
+                error!("Failed to store location of user {}: {:?}", user_id, e);
+            }
+        }
         LocationCommand::handle(&bot, msg.chat.id, location.latitude, location.longitude).await?;
         return Ok(());
     }
@@ -95,7 +185,63 @@ pub async fn message_handler(
                 if msg.chat.id == ChatId(ADMIN_ID) {
                     let parts: Vec<&str> = text.split_whitespace().collect();
                     let route = if parts.len() > 1 { parts[1] } else { "" };
-                    BroadcastCommand::send_to_all(&bot, msg.chat.id, route).await?;
+                    let audience = parts.get(2..).unwrap_or_default().join(" ");
+                    BroadcastCommand::send_to_all(&bot, msg.chat.id, route, &audience).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Audience) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let parts: Vec<&str> = text.split_whitespace().collect();
+                    let audience = parts.get(1..).unwrap_or_default().join(" ");
+                    BroadcastCommand::preview_audience(&bot, msg.chat.id, &audience).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Compose) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    ComposeCommand::start(&bot, msg.chat.id).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::SendDraft) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let parts: Vec<&str> = text.split_whitespace().collect();
+                    let audience = parts.get(1..).unwrap_or_default().join(" ");
+                    ComposeCommand::send_draft(&bot, msg.chat.id, &audience).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Schedule) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let parts: Vec<&str> = text.split_whitespace().collect();
+                    ScheduleCommand::schedule(&bot, msg.chat.id, &parts[1..]).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Scheduled) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    ScheduleCommand::list(&bot, msg.chat.id).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Unschedule) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let parts: Vec<&str> = text.split_whitespace().collect();
+                    let record_id = if parts.len() > 1 { parts[1] } else { "" };
+                    ScheduleCommand::cancel(&bot, msg.chat.id, record_id).await?;
                 } else {
                     bot.send_message(msg.chat.id, send_unknown_command_message(text))
                         .await?;
@@ -111,23 +257,132 @@ pub async fn message_handler(
                         .await?;
                 }
             }
-            Ok(Command::AdventTest) => {
+            Ok(Command::CampaignTest) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let key = text.split_whitespace().nth(1);
+                    CampaignCommand::send_test(&bot, msg.chat.id, TEST_USER_ID, key).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Stats) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    StatsCommand::handle(&bot, msg.chat.id).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::User) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let username = text.split_whitespace().nth(1);
+                    StatsCommand::user(&bot, msg.chat.id, username).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Message) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let mut parts = text.splitn(3, char::is_whitespace);
+                    let username = parts.nth(1).filter(|username| !username.is_empty());
+                    let body = parts.next().unwrap_or_default();
+                    StatsCommand::message(&bot, msg.chat.id, username, body).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Broadcasts) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    BroadcastCommand::history(&bot, msg.chat.id).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Reload) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    ReloadCommand::handle(&bot, msg.chat.id).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Source) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let route = text.split_whitespace().nth(1);
+                    EditCommand::source(&bot, msg.chat.id, route).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Edit) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let route = text.split_whitespace().nth(1);
+                    EditCommand::request(&bot, msg.chat.id, route).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Cancel) => {
                 if msg.chat.id == ChatId(ADMIN_ID) {
-                    AdventCommand::send_test(&bot, msg.chat.id, TEST_USER_ID).await?;
+                    EditCommand::cancel(&bot, msg.chat.id).await?;
                 } else {
                     bot.send_message(msg.chat.id, send_unknown_command_message(text))
                         .await?;
                 }
             }
+            Ok(Command::Versions) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let route = text.split_whitespace().nth(1);
+                    EditCommand::versions(&bot, msg.chat.id, route).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Leaderboard) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let key = text.split_whitespace().nth(1);
+                    if let Some(campaign) = CampaignCommand::lookup(&bot, msg.chat.id, key).await? {
+                        SubmissionCommand::leaderboard(&bot, msg.chat.id, campaign).await?;
+                    }
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Draw) => {
+                if msg.chat.id == ChatId(ADMIN_ID) {
+                    let args = text
+                        .split_once(char::is_whitespace)
+                        .map_or("", |(_, args)| args);
+                    DrawCommand::handle(&bot, msg.chat.id, args).await?;
+                } else {
+                    bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                        .await?;
+                }
+            }
+            Ok(Command::Calendar) => {
+                let key = text.split_whitespace().nth(1);
+                CampaignCommand::offer_catch_up(&bot, msg.chat.id, key).await?;
+            }
             Ok(Command::Stop) => {
                 if let Some(user) = msg.from() {
                     let user_id: i64 = user.id.0.try_into().unwrap();
                     StopCommand::handle(&bot, msg.chat.id, user_id).await?;
                 }
             }
-            Ok(Command::Advent) => {
+            Ok(Command::Campaign) => {
                 if msg.chat.id == ChatId(ADMIN_ID) {
-                    AdventCommand::send_to_all(&bot, msg.chat.id).await?;
+                    let mut args = text.split_whitespace().skip(1);
+                    let key = args.next();
+                    let force = args.next() == Some("force");
+                    CampaignCommand::request_send_to_all(&bot, msg.chat.id, key, force).await?;
                 } else {
                     bot.send_message(msg.chat.id, send_unknown_command_message(text))
                         .await?;
@@ -138,7 +393,7 @@ pub async fn message_handler(
                 | Command::About
                 | Command::Recycling
                 | Command::GiveAway
-                | Command::FAQ
+                | Command::Faq
                 | Command::Plastic
                 | Command::Paper
                 | Command::Metal
@@ -151,18 +406,22 @@ pub async fn message_handler(
             }
             Err(_) => {
                 match text {
-                    "бот" | "Бот" => {
+                    text if MENU_WORDS.contains(&text) => {
                         ContentCommand::send(&bot, msg.chat.id, "start").await?;
                     }
-                    "стоп" | "Стоп" | "СТОП" => {
+                    text if STOP_WORDS.contains(&text) => {
                         if let Some(user) = msg.from() {
                             let user_id: i64 = user.id.0.try_into().unwrap();
                             StopCommand::handle(&bot, msg.chat.id, user_id).await?;
                         }
                     }
                     _ => {
-                        bot.send_message(msg.chat.id, send_unknown_command_message(text))
-                            .await?;
+                        if let Some(route) = route::find_by_keyword(text) {
+                            ContentCommand::send(&bot, msg.chat.id, &route).await?;
+                        } else {
+                            bot.send_message(msg.chat.id, send_unknown_command_message(text))
+                                .await?;
+                        }
                     }
                 };
             }
@@ -183,30 +442,136 @@ pub async fn callback_handler(
             info!("New user registered from callback: {}", user_id);
         }
     }
+    if let Err(e) = users::track_activity(&q.from, Activity::Callback).await {
+        error!("Failed to track activity of user {}: {:?}", user_id, e);
+    }
 
-    if let Some(ref text) = q.data {
-        log::info!("callback: {}", text);
+    if let Some(ref data) = q.data {
+        log::info!("callback: {}", data);
+        let Some(action) = CallbackAction::decode(data) else {
+            bot.answer_callback_query(&q.id)
+                .text("Эта кнопка устарела, открываю главное меню.")
+                .await?;
+            ContentCommand::send(&bot, q.from.id.into(), ROOT).await?;
+            return Ok(());
+        };
         bot.answer_callback_query(&q.id).await?;
 
-        // Handle subscribe/unsubscribe actions
-        if text.starts_with("/subscribe_") {
-            let subscription_type = text.strip_prefix("/subscribe_").unwrap();
-            let _ = SubscriptionCommand::subscribe(&bot, q.from.id, subscription_type, text).await;
-            return Ok(());
-        } else if text.starts_with("/unsubscribe_") {
-            let subscription_type = text.strip_prefix("/unsubscribe_").unwrap();
-            let _ =
-                SubscriptionCommand::unsubscribe(&bot, q.from.id, subscription_type, text).await;
-            return Ok(());
+        let user_id_i64: i64 = user_id.try_into().unwrap();
+        let is_admin = user_id_i64 == ADMIN_ID;
+        let message_id = q.message.as_ref().map(|message| message.id);
+
+        // Count presses on broadcast buttons, then handle the original action. A broadcast stays
+        // in the chat, so its buttons open pages as new messages
+        let (action, clicked) = match action {
+            CallbackAction::Tracked { broadcast, action } => {
+                if let Err(e) =
+                    db::record_broadcast_click(&broadcast, &action.to_string(), user_id_i64).await
+                {
+                    error!("Failed to record broadcast click: {:?}", e);
+                }
+                (*action, None)
+            }
+            action => (action, q.message.as_ref()),
+        };
+
+        match action {
+            // Menu navigation edits the clicked message in place
+            CallbackAction::Navigate(route) => {
+                if let Err(e) =
+                    ContentCommand::navigate(&bot, clicked, &route, 0, user_id_i64).await
+                {
+                    error!("Error sending message: {:?}", e);
+                    bot.send_message(q.from.id, e.to_string()).await?;
+                }
+            }
+            CallbackAction::Paginate { route, page } => {
+                if let Err(e) =
+                    ContentCommand::navigate(&bot, clicked, &route, page, user_id_i64).await
+                {
+                    error!("Error sending message: {:?}", e);
+                    bot.send_message(q.from.id, e.to_string()).await?;
+                }
+            }
+            CallbackAction::Subscribe(kind) => {
+                let _ = SubscriptionCommand::subscribe(&bot, q.from.id, &kind).await;
+            }
+            CallbackAction::Unsubscribe(kind) => {
+                let _ = SubscriptionCommand::unsubscribe(&bot, q.from.id, &kind).await;
+            }
+            CallbackAction::Report { campaign, day } => {
+                if let Err(e) = SubmissionCommand::request(&bot, user_id_i64, &campaign, day).await
+                {
+                    error!("Error requesting campaign report: {:?}", e);
+                }
+            }
+            CallbackAction::CancelReport => {
+                SubmissionCommand::cancel(&bot, user_id_i64).await?;
+            }
+            CallbackAction::CatchUp { campaign, day } => {
+                if let Err(e) =
+                    CampaignCommand::send_past_day(&bot, user_id_i64, &campaign, day).await
+                {
+                    error!("Error sending past campaign day: {:?}", e);
+                }
+            }
+            CallbackAction::Confirm { scope, verb, id } if is_admin => {
+                let admin_chat_id = ChatId(ADMIN_ID);
+                let result = match scope {
+                    ConfirmScope::Broadcast => {
+                        BroadcastCommand::control(&bot, admin_chat_id, &verb, &id).await
+                    }
+                    ConfirmScope::Campaign => {
+                        CampaignCommand::control(&bot, admin_chat_id, &verb, &id, message_id).await
+                    }
+                    ConfirmScope::Submission => {
+                        SubmissionCommand::review(&bot, admin_chat_id, &verb, &id, message_id).await
+                    }
+                    ConfirmScope::Content => {
+                        EditCommand::control(&bot, admin_chat_id, &verb, &id).await
+                    }
+                };
+                if let Err(e) = result {
+                    error!("Error handling {:?} {} {}: {:?}", scope, verb, id, e);
+                }
+            }
+            CallbackAction::Link { broadcast, button } => {
+                if let Err(e) =
+                    BroadcastCommand::open_link(&bot, user_id_i64, &broadcast, button).await
+                {
+                    error!("Error opening broadcast link: {:?}", e);
+                }
+            }
+            // Admin buttons pressed by anyone else, and tracking nested in tracking
+            CallbackAction::Confirm { .. } | CallbackAction::Tracked { .. } => {}
         }
+    }
 
-        // Handle regular content navigation
-        let user_id_i64: i64 = user_id.try_into().unwrap();
-        if let Err(e) =
-            ContentCommand::send_with_user(&bot, q.from.id.into(), text, user_id_i64).await
-        {
-            error!("Error sending message: {:?}", e);
-            bot.send_message(q.from.id, e.to_string()).await?;
+    Ok(())
+}
+
+pub async fn my_chat_member_handler(
+    _bot: Bot,
+    update: ChatMemberUpdated,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Only private chats matter: there the member is the user who blocked or unblocked the bot
+    if !update.chat.is_private() {
+        return Ok(());
+    }
+
+    let user_id: i64 = update.chat.id.0;
+    let new_member = &update.new_chat_member.kind;
+    if new_member.is_banned() || new_member.is_left() {
+        info!("User {} blocked the bot", user_id);
+        if let Err(e) = users::blacklist_user(user_id).await {
+            error!("Failed to blacklist user {}: {:?}", user_id, e);
+        }
+    } else if new_member.is_present() {
+        info!("User {} unblocked the bot", user_id);
+        match users::store_user(user_id).await {
+            Ok(true) => info!("New user registered from chat member update: {}", user_id),
+            Ok(false) => {}
+            Err(e) => error!("Failed to store user {}: {:?}", user_id, e),
         }
     }
 
@@ -1,8 +1,19 @@
 #![allow(dead_code)]
 #![allow(unused)]
 
+pub mod audience;
+pub mod broadcaster;
+pub mod callback;
+pub mod campaign;
 pub mod commands;
+pub mod contents;
 pub mod db;
+pub mod delivery;
 pub mod handlers;
+pub mod local_time;
+pub mod media;
+pub mod page;
 pub mod route;
+pub mod scheduler;
 pub mod users;
+pub mod validation;
@@ -0,0 +1,38 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
+
+/// Kaliningrad time (UTC+2, no daylight saving time)
+pub fn kaliningrad() -> FixedOffset {
+    FixedOffset::east_opt(2 * 3600).unwrap()
+}
+
+/// Parse a Kaliningrad date and time like `2025-12-01 10:00` or `2025-12-01T10:00`
+pub fn parse_local_datetime(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim().replace('T', " ");
+    let naive = NaiveDateTime::parse_from_str(&text, "%Y-%m-%d %H:%M").ok()?;
+    kaliningrad()
+        .from_local_datetime(&naive)
+        .single()
+        .map(|local| local.with_timezone(&Utc))
+}
+
+/// Format a moment as Kaliningrad date and time
+pub fn format_local_datetime(moment: DateTime<Utc>) -> String {
+    moment
+        .with_timezone(&kaliningrad())
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_local_datetime() {
+        let moment = parse_local_datetime("2025-12-01 10:00").unwrap();
+        assert_eq!(moment.to_rfc3339(), "2025-12-01T08:00:00+00:00");
+        assert_eq!(parse_local_datetime("2025-12-01T10:00"), Some(moment));
+        assert_eq!(format_local_datetime(moment), "2025-12-01 10:00");
+        assert_eq!(parse_local_datetime("01.12.2025 10:00"), None);
+    }
+}
@@ -3,17 +3,28 @@
 
 use dotenv::dotenv;
 use env_logger::{Builder, Target};
-use handlers::{callback_handler, message_handler};
+use handlers::{callback_handler, message_handler, my_chat_member_handler};
 use std::env;
 use std::fs::OpenOptions;
 use std::io::Write;
 use teloxide::prelude::*;
 
+mod audience;
+mod broadcaster;
+mod callback;
+mod campaign;
 mod commands;
+mod contents;
 mod db;
+mod delivery;
 mod handlers;
+mod local_time;
+mod media;
+mod page;
 mod route;
+mod scheduler;
 mod users;
+mod validation;
 
 fn init_logging() {
     let log_path = std::env::var("LOG_PATH").unwrap_or_else(|_| "ecobot.log".to_string());
@@ -55,13 +66,20 @@ async fn main() -> anyhow::Result<()> {
     //     .expect("Failed to store ESSO points");
 
     log::info!("Database connected successfully");
+    if let Err(e) = contents::load_edited().await {
+        log::error!("Failed to load pages edited from Telegram: {:?}", e);
+    }
 
     let bot = Bot::new(&telegram_bot_token);
+    broadcaster::spawn_worker(bot.clone());
+    scheduler::spawn_scheduler(bot.clone());
+    contents::spawn_watcher(bot.clone());
     log::info!("Bot initialized, starting dispatcher...");
 
     let handler = dptree::entry()
         .branch(Update::filter_message().endpoint(message_handler))
-        .branch(Update::filter_callback_query().endpoint(callback_handler));
+        .branch(Update::filter_callback_query().endpoint(callback_handler))
+        .branch(Update::filter_my_chat_member().endpoint(my_chat_member_handler));
     Dispatcher::builder(bot, handler)
         .enable_ctrlc_handler()
         .build()
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use rust_embed::RustEmbed;
+use teloxide::{
+    types::{InputFile, Message},
+    ApiError, RequestError,
+};
+
+use crate::db;
+
+/// Images and documents sent by content pages and campaigns
+#[derive(RustEmbed)]
+#[folder = "src/assets/"]
+pub struct Assets;
+
+/// Known `file_id`s by content hash, in front of the database
+static FILE_IDS: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Held while files are uploaded, so concurrent sends wait and reuse their `file_id`s
+static UPLOAD: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// Hex SHA-256 of an embedded file: a changed file is uploaded again
+fn content_hash(name: &str) -> Option<String> {
+    let file = Assets::get(name)?;
+    Some(
+        file.metadata
+            .sha256_hash()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    )
+}
+
+/// Whether a media entry is an external file rather than one of the assets
+pub fn is_url(name: &str) -> bool {
+    name.starts_with("http://") || name.starts_with("https://")
+}
+
+/// Telegram file of the largest photo, video, animation or document in a message
+pub fn message_file_id(message: &Message) -> Option<String> {
+    if let Some(sizes) = message.photo() {
+        return sizes.last().map(|photo| photo.file.id.clone());
+    }
+    message
+        .video()
+        .map(|video| video.file.id.clone())
+        .or_else(|| {
+            message
+                .animation()
+                .map(|animation| animation.file.id.clone())
+        })
+        .or_else(|| message.document().map(|document| document.file.id.clone()))
+}
+
+async fn cached_file_id(hash: &str) -> Option<String> {
+    if let Some(file_id) = FILE_IDS.lock().unwrap().get(hash) {
+        return Some(file_id.clone());
+    }
+    match db::get_media_file_id(hash).await {
+        Ok(Some(file_id)) => {
+            FILE_IDS
+                .lock()
+                .unwrap()
+                .insert(hash.to_string(), file_id.clone());
+            Some(file_id)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("Failed to load media file {}: {:?}", hash, e);
+            None
+        }
+    }
+}
+
+/// Forget a `file_id` Telegram rejected, in memory and in the database
+async fn forget(hash: &str) {
+    FILE_IDS.lock().unwrap().remove(hash);
+    if let Err(e) = db::delete_media_file_id(hash).await {
+        error!("Failed to delete file_id of {}: {:?}", hash, e);
+    }
+}
+
+/// Whether Telegram refused a `file_id`, e.g. one issued to another bot token
+fn is_rejected_file_id(error: &RequestError) -> bool {
+    match error {
+        RequestError::Api(
+            ApiError::WrongFileId | ApiError::WrongFileIdOrUrl | ApiError::FileIdInvalid,
+        ) => true,
+        RequestError::Api(ApiError::Unknown(message)) => {
+            let message = message.to_lowercase();
+            message.contains("file identifier") || message.contains("file id")
+        }
+        _ => false,
+    }
+}
+
+/// Files to send, as a cached `file_id` or an upload
+struct Resolved {
+    files: Vec<InputFile>,
+    /// Content hash to remember the `file_id` of each uploaded file under
+    uploads: Vec<Option<String>>,
+    /// Content hashes of the files sent by a cached `file_id`
+    cached: Vec<String>,
+}
+
+async fn resolve(names: &[&str]) -> Result<Resolved, Box<dyn std::error::Error + Send + Sync>> {
+    let mut resolved = Resolved {
+        files: vec![],
+        uploads: vec![],
+        cached: vec![],
+    };
+    for name in names {
+        if is_url(name) {
+            resolved.files.push(InputFile::url(Url::parse(name)?));
+            resolved.uploads.push(None);
+            continue;
+        }
+
+        let hash = content_hash(name).ok_or_else(|| format!("Asset {} not found", name))?;
+        if let Some(file_id) = cached_file_id(&hash).await {
+            resolved.files.push(InputFile::file_id(file_id));
+            resolved.uploads.push(None);
+            resolved.cached.push(hash);
+            continue;
+        }
+        let file = Assets::get(name).ok_or_else(|| format!("Asset {} not found", name))?;
+        resolved
+            .files
+            .push(InputFile::memory(file.data.into_owned()).file_name(name.to_string()));
+        resolved.uploads.push(Some(hash));
+    }
+    Ok(resolved)
+}
+
+async fn remember(hash: &str, name: &str, message: &Message) {
+    let Some(file_id) = message_file_id(message) else {
+        return;
+    };
+    info!("Uploaded {} as {}", name, file_id);
+    FILE_IDS
+        .lock()
+        .unwrap()
+        .insert(hash.to_string(), file_id.clone());
+    if let Err(e) = db::set_media_file_id(hash, name, &file_id).await {
+        error!("Failed to store file_id of {}: {:?}", name, e);
+    }
+}
+
+/// Whether some of the files still have to be uploaded
+async fn needs_upload(names: &[&str]) -> bool {
+    for name in names {
+        if let Some(hash) = content_hash(name) {
+            if cached_file_id(&hash).await.is_none() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Send a file from `src/assets` (or an external `http(s)` URL), uploading an embedded file
+/// only once and reusing its Telegram `file_id` afterwards
+pub async fn send_media<F, Fut>(
+    name: &str,
+    send: F,
+) -> Result<Message, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: Fn(InputFile) -> Fut,
+    Fut: Future<Output = Result<Message, RequestError>>,
+{
+    let messages = send_album(&[name], |mut files| {
+        let send = &send;
+        async move { Ok(vec![send(files.remove(0)).await?]) }
+    })
+    .await?;
+    messages
+        .into_iter()
+        .next()
+        .ok_or_else(|| "No message was sent".into())
+}
+
+/// Send several files at once, like a media group, with the same upload-once caching
+pub async fn send_album<F, Fut>(
+    names: &[&str],
+    send: F,
+) -> Result<Vec<Message>, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: Fn(Vec<InputFile>) -> Fut,
+    Fut: Future<Output = Result<Vec<Message>, RequestError>>,
+{
+    // Another send may be uploading the same files: wait for it and reuse their file_ids
+    let mut _upload = if needs_upload(names).await {
+        Some(UPLOAD.lock().await)
+    } else {
+        None
+    };
+
+    let Resolved {
+        files,
+        mut uploads,
+        cached,
+    } = resolve(names).await?;
+    let messages = match send(files).await {
+        Ok(messages) => messages,
+        // A rejected file_id stays rejected: forget the cached ones and upload the files again
+        Err(e) if !cached.is_empty() && is_rejected_file_id(&e) => {
+            warn!(
+                "Cached file_ids of {:?} rejected, uploading again: {:?}",
+                names, e
+            );
+            for hash in &cached {
+                forget(hash).await;
+            }
+            if _upload.is_none() {
+                _upload = Some(UPLOAD.lock().await);
+            }
+            let resolved = resolve(names).await?;
+            uploads = resolved.uploads;
+            send(resolved.files).await?
+        }
+        Err(e) => return Err(e.into()),
+    };
+    for ((name, hash), message) in names.iter().zip(&uploads).zip(&messages) {
+        if let Some(hash) = hash {
+            remember(hash, name, message).await;
+        }
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_rejected_file_id() {
+        assert!(is_rejected_file_id(&RequestError::Api(
+            ApiError::WrongFileIdOrUrl
+        )));
+        assert!(is_rejected_file_id(&RequestError::Api(ApiError::Unknown(
+            "Bad Request: wrong remote file identifier specified: Wrong string length".to_string()
+        ))));
+        assert!(!is_rejected_file_id(&RequestError::Api(
+            ApiError::BotBlocked
+        )));
+        assert!(is_url("http://ecoklgd.ru/poster.jpg"));
+        assert!(!is_url("25.jpg"));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let hash = content_hash("25.jpg").unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(content_hash("25.jpg"), Some(hash));
+        assert_eq!(content_hash("missing.jpg"), None);
+    }
+}
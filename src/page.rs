@@ -0,0 +1,378 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use teloxide::types::ParseMode;
+
+use crate::local_time::parse_local_datetime;
+use crate::media::{is_url, Assets};
+
+/// Telegram's limit for media captions, in characters
+pub const CAPTION_LIMIT: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaKind {
+    Photo,
+    Video,
+    Document,
+}
+
+impl MediaKind {
+    /// Guess how to send a file from its extension
+    fn from_file(file: &str) -> Self {
+        let extension = file.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("jpg" | "jpeg" | "png" | "webp") => MediaKind::Photo,
+            Some("mp4" | "mov") => MediaKind::Video,
+            _ => MediaKind::Document,
+        }
+    }
+}
+
+/// File attached to a content page: a name in `src/assets` or an external URL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Media {
+    pub kind: MediaKind,
+    pub file: String,
+}
+
+/// How Telegram should render the page body
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PageFormat {
+    #[default]
+    Html,
+    Markdown,
+    Plain,
+}
+
+impl PageFormat {
+    pub fn parse_mode(self) -> Option<ParseMode> {
+        match self {
+            PageFormat::Html => Some(ParseMode::Html),
+            PageFormat::Markdown => Some(ParseMode::MarkdownV2),
+            PageFormat::Plain => None,
+        }
+    }
+
+    /// Plain text set in italics, escaped for this format
+    pub fn italic(self, text: &str) -> String {
+        match self {
+            PageFormat::Html => format!(
+                "<i>{}</i>",
+                text.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+            ),
+            PageFormat::Markdown => {
+                let mut escaped = String::new();
+                for c in text.chars() {
+                    if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                format!("_{}_", escaped)
+            }
+            PageFormat::Plain => text.to_string(),
+        }
+    }
+}
+
+/// Content file split into its front-matter and its body
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Page {
+    pub title: Option<String>,
+    /// Text of the button that leads to the page
+    pub label: Option<String>,
+    /// Page whose menu lists this one
+    pub parent: Option<String>,
+    /// Position among the pages of the same parent
+    pub order: i64,
+    pub media: Vec<Media>,
+    /// Words that open the page when a user types them
+    pub keywords: Vec<String>,
+    pub parse_mode: PageFormat,
+    pub valid_from: Option<DateTime<Utc>>,
+    pub valid_until: Option<DateTime<Utc>>,
+    /// Label and URL of the button shown instead of the menu in broadcasts
+    pub external: Option<(String, String)>,
+    /// Set by `breadcrumb: false` for nested pages that read better without the path on top
+    pub hide_breadcrumb: bool,
+    pub body: String,
+}
+
+/// Value of a front-matter key: a scalar, or a `[a, b]` / `- item` list
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Scalar(String),
+    List(Vec<String>),
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return inner.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// Parse the YAML subset used in front-matter: `key: value` lines with scalars,
+/// inline `[a, b]` lists and block lists of `- item` lines
+fn parse_front_matter(header: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut entries: Vec<(String, Value)> = vec![];
+    for line in header.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if let Some(item) = trimmed.strip_prefix("- ") {
+            match entries.last_mut() {
+                Some((_, Value::List(items))) if line.starts_with(char::is_whitespace) => {
+                    items.push(unquote(item))
+                }
+                _ => return Err(format!("List item without a key: {}", line)),
+            }
+            continue;
+        }
+
+        let (key, value) = trimmed
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid front-matter line: {}", line))?;
+        let value = value.trim();
+        let value = if value.is_empty() {
+            Value::List(vec![])
+        } else if let Some(items) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Value::List(
+                items
+                    .split(',')
+                    .map(unquote)
+                    .filter(|item| !item.is_empty())
+                    .collect(),
+            )
+        } else {
+            Value::Scalar(unquote(value))
+        };
+        entries.push((key.trim().to_string(), value));
+    }
+    Ok(entries)
+}
+
+/// Kaliningrad `2025-12-01 10:00`, or a date meaning its midnight
+fn parse_moment(value: &str) -> Option<DateTime<Utc>> {
+    parse_local_datetime(value).or_else(|| {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| parse_local_datetime(&format!("{} 00:00", date)))
+    })
+}
+
+impl Page {
+    /// Parse an optional front-matter block between `---` lines:
+    ///
+    /// ```text
+    /// ---
+    /// label: Пластик
+    /// parent: recycling
+    /// order: 2
+    /// media: [bins.jpg, sorting_guide.pdf]
+    /// keywords: [пластик, бутылки]
+    /// valid_until: 2025-12-31
+    /// ---
+    /// Page text
+    /// ```
+    pub fn parse(text: &str) -> Result<Self, String> {
+        // Files saved on Windows would otherwise lose their front-matter to the body
+        let text = text.replace("\r\n", "\n");
+        let Some(rest) = text.strip_prefix("---\n") else {
+            return Ok(Page {
+                body: text,
+                ..Page::default()
+            });
+        };
+        let (header, body) = match rest.split_once("\n---\n") {
+            Some(parts) => parts,
+            None => rest
+                .strip_suffix("\n---")
+                .map(|header| (header, ""))
+                .ok_or("Front-matter is not closed with ---")?,
+        };
+
+        let mut page = Page {
+            body: body.trim_start_matches('\n').to_string(),
+            ..Page::default()
+        };
+        for (key, value) in parse_front_matter(header)? {
+            let invalid = || format!("Invalid value of {}", key);
+            match (key.as_str(), value) {
+                ("title", Value::Scalar(value)) => page.title = Some(value),
+                ("label", Value::Scalar(value)) => page.label = Some(value),
+                ("parent", Value::Scalar(value)) => page.parent = Some(value),
+                ("order", Value::Scalar(value)) => {
+                    page.order = value.parse().map_err(|_| invalid())?
+                }
+                ("media", Value::List(files)) => {
+                    page.media = files
+                        .into_iter()
+                        .map(|file| Media {
+                            kind: MediaKind::from_file(&file),
+                            file,
+                        })
+                        .collect()
+                }
+                ("media", Value::Scalar(file)) => {
+                    page.media = vec![Media {
+                        kind: MediaKind::from_file(&file),
+                        file,
+                    }]
+                }
+                ("keywords", Value::List(keywords)) => {
+                    page.keywords = keywords.iter().map(|k| k.to_lowercase()).collect()
+                }
+                ("parse_mode", Value::Scalar(value)) => {
+                    page.parse_mode = match value.to_lowercase().as_str() {
+                        "html" => PageFormat::Html,
+                        "markdown" | "markdownv2" => PageFormat::Markdown,
+                        "plain" | "none" => PageFormat::Plain,
+                        _ => return Err(invalid()),
+                    }
+                }
+                ("valid_from", Value::Scalar(value)) => {
+                    page.valid_from = Some(parse_moment(&value).ok_or_else(invalid)?)
+                }
+                ("valid_until", Value::Scalar(value)) => {
+                    page.valid_until = Some(parse_moment(&value).ok_or_else(invalid)?)
+                }
+                ("external", Value::List(link)) => match link.as_slice() {
+                    [label, url] => page.external = Some((label.clone(), url.clone())),
+                    _ => return Err(invalid()),
+                },
+                ("breadcrumb", Value::Scalar(value)) => {
+                    page.hide_breadcrumb = match value.as_str() {
+                        "true" => false,
+                        "false" => true,
+                        _ => return Err(invalid()),
+                    }
+                }
+                (
+                    "title" | "label" | "parent" | "order" | "keywords" | "parse_mode"
+                    | "valid_from" | "valid_until" | "external" | "breadcrumb",
+                    _,
+                ) => return Err(invalid()),
+                _ => return Err(format!("Unknown front-matter key: {}", key)),
+            }
+        }
+
+        // Telegram albums group documents only with documents
+        let documents = page
+            .media
+            .iter()
+            .filter(|media| media.kind == MediaKind::Document)
+            .count();
+        if documents > 0 && documents < page.media.len() {
+            return Err("Documents can't be mixed with photos or videos".to_string());
+        }
+        if page.media.len() > 10 {
+            return Err("An album holds at most 10 files".to_string());
+        }
+
+        Ok(page)
+    }
+
+    /// Whether the page is shown at `now`, according to `valid_from` and `valid_until`
+    pub fn is_available(&self, now: DateTime<Utc>) -> bool {
+        self.valid_from.is_none_or(|from| from <= now)
+            && self.valid_until.is_none_or(|until| now < until)
+    }
+
+    /// Attached files missing from `src/assets`
+    pub fn missing_media(&self) -> Vec<&str> {
+        self.media
+            .iter()
+            .map(|media| media.file.as_str())
+            .filter(|file| !is_url(file) && Assets::get(file).is_none())
+            .collect()
+    }
+
+    /// Whether the body can go out as the caption of a single file. Counted on the
+    /// source text, which is never shorter than the text Telegram measures
+    pub fn fits_caption(&self) -> bool {
+        self.media.len() == 1 && self.body.chars().count() <= CAPTION_LIMIT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_page() {
+        let page = Page::parse(
+            "---\nlabel: \"Пластик: что сдавать\"\nparent: recycling\norder: 2\nmedia:\n  - 25.jpg\n  - https://example.com/bins.jpg\nkeywords: [Пластик, бутылки]\n---\n<b>Пластик</b>\n",
+        )
+        .unwrap();
+        assert_eq!(page.label.as_deref(), Some("Пластик: что сдавать"));
+        assert_eq!(page.parent.as_deref(), Some("recycling"));
+        assert_eq!(page.order, 2);
+        assert_eq!(page.media.len(), 2);
+        assert_eq!(page.media[0].kind, MediaKind::Photo);
+        assert_eq!(page.keywords, vec!["пластик", "бутылки"]);
+        assert_eq!(page.parse_mode, PageFormat::Html);
+        assert_eq!(page.body, "<b>Пластик</b>\n");
+        assert!(page.missing_media().is_empty());
+        assert!(!page.fits_caption());
+
+        let plain = Page::parse("Просто текст\n---\n").unwrap();
+        assert!(plain.media.is_empty());
+        assert_eq!(plain.body, "Просто текст\n---\n");
+
+        let guide = Page::parse(
+            "---\nmedia: guide.pdf\nparse_mode: plain\nbreadcrumb: false\n---\nПамятка",
+        )
+        .unwrap();
+        assert!(guide.hide_breadcrumb);
+        assert_eq!(PageFormat::Html.italic("A & B"), "<i>A &amp; B</i>");
+        assert_eq!(PageFormat::Markdown.italic("A › B."), "_A › B\\._");
+        assert_eq!(guide.media[0].kind, MediaKind::Document);
+        assert_eq!(guide.parse_mode.parse_mode(), None);
+        assert!(guide.fits_caption());
+        assert_eq!(guide.missing_media(), vec!["guide.pdf"]);
+
+        assert!(Page::parse("---\nmedia: 25.jpg\nПамятка").is_err());
+        assert!(Page::parse("---\naudio: song.mp3\n---\n").is_err());
+        assert!(Page::parse("---\norder: first\n---\n").is_err());
+        assert!(Page::parse("---\nmedia: [25.jpg, guide.pdf]\n---\n").is_err());
+        assert!(Page::parse("---\n- 25.jpg\n---\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_crlf_page() {
+        let page =
+            Page::parse("---\r\nlabel: Стекло\r\nparent: recycling\r\n---\r\nСтрока 1\r\nСтрока 2")
+                .unwrap();
+        assert_eq!(page.label.as_deref(), Some("Стекло"));
+        assert_eq!(page.parent.as_deref(), Some("recycling"));
+        assert_eq!(page.body, "Строка 1\nСтрока 2");
+    }
+
+    #[test]
+    fn test_is_available() {
+        let page = Page::parse("---\nvalid_from: 2025-12-01\nvalid_until: 2025-12-31 18:00\n---\n")
+            .unwrap();
+        assert!(!page.is_available(parse_local_datetime("2025-11-30 23:59").unwrap()));
+        assert!(page.is_available(parse_local_datetime("2025-12-01 00:00").unwrap()));
+        assert!(!page.is_available(parse_local_datetime("2025-12-31 18:00").unwrap()));
+        assert!(Page::default().is_available(Utc::now()));
+    }
+
+    #[test]
+    fn test_fits_caption() {
+        let mut page = Page::parse("---\nmedia: 25.jpg\n---\n").unwrap();
+        page.body = "а".repeat(CAPTION_LIMIT);
+        assert!(page.fits_caption());
+        page.body.push('а');
+        assert!(!page.fits_caption());
+    }
+}
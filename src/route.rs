@@ -1,24 +1,141 @@
-use serde::{Deserialize, Serialize};
+use chrono::Utc;
+use log::error;
 use std::collections::HashMap;
+use std::env;
 
+use crate::callback::CallbackAction;
+use crate::contents::{self, ContentSet};
 use crate::db;
-use once_cell::sync::Lazy;
+use crate::page::Page;
 use reqwest::Url;
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
-pub static ROUTES: Lazy<HashMap<String, Route>> = Lazy::new(|| routes().unwrap());
+/// Top of the route tree, reached by "На Главную"
+pub const ROOT: &str = "start";
+/// Nested this deep or deeper, pages start with their path from the top
+const BREADCRUMB_DEPTH: usize = 3;
+/// Children shown on one page of a menu when `MENU_PAGE_SIZE` is not set
+const DEFAULT_MENU_PAGE_SIZE: usize = 8;
 
-#[derive(Serialize, Deserialize)]
 pub struct Route {
-    pub path: String,
     pub label: String,
+    pub parent: Option<String>,
     pub children: Option<Vec<String>>,
     pub external: Option<Vec<String>>,
 }
 
-pub fn routes() -> Result<HashMap<String, Route>, serde_json::Error> {
-    let routes: HashMap<String, Route> = serde_json::from_str(include_str!("./routes.json"))?;
-    Ok(routes)
+/// Route tree of the pages: children come from `parent`, ordered by `order`
+pub fn routes_from_pages(pages: &HashMap<String, Page>) -> HashMap<String, Route> {
+    let mut children: HashMap<&str, Vec<(i64, &str)>> = HashMap::new();
+    for (route, page) in pages {
+        if let Some(parent) = &page.parent {
+            children
+                .entry(parent.as_str())
+                .or_default()
+                .push((page.order, route.as_str()));
+        }
+    }
+
+    pages
+        .iter()
+        .map(|(route, page)| {
+            let children = children.get(route.as_str()).map(|children| {
+                let mut children = children.clone();
+                children.sort();
+                children
+                    .into_iter()
+                    .map(|(_, child)| child.to_string())
+                    .collect()
+            });
+            let label = page
+                .label
+                .clone()
+                .or_else(|| page.title.clone())
+                .unwrap_or_else(|| route.clone());
+            let external = page.external.clone().map(|(label, url)| vec![label, url]);
+
+            (
+                route.clone(),
+                Route {
+                    label,
+                    parent: page.parent.clone(),
+                    children,
+                    external,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Labels from the top of the tree down to the route
+pub fn breadcrumb(content: &ContentSet, route: &str) -> Vec<String> {
+    let mut labels = vec![];
+    let mut current = Some(route);
+    // Bounded by the number of routes, in case a cycle got past validation
+    while let Some(key) = current.filter(|_| labels.len() <= content.routes.len()) {
+        let Some(route) = content.routes.get(key) else {
+            break;
+        };
+        labels.push(route.label.clone());
+        current = route.parent.as_deref();
+    }
+    labels.reverse();
+    labels
+}
+
+/// Breadcrumb line for pages nested deep enough, e.g. "Главная › Переработка › Пластик"
+pub fn breadcrumb_line(content: &ContentSet, route: &str) -> Option<String> {
+    let labels = breadcrumb(content, route);
+    (labels.len() >= BREADCRUMB_DEPTH).then(|| labels.join(" › "))
+}
+
+/// "Назад" button to the parent of a route, unless the parent is the top reached by "На Главную"
+fn back_button(content: &ContentSet, route: &Route) -> Option<InlineKeyboardButton> {
+    let parent = route.parent.as_deref().filter(|parent| *parent != ROOT)?;
+    content
+        .routes
+        .contains_key(parent)
+        .then(|| CallbackAction::Navigate(parent.to_string()).button("⬅️ Назад"))
+}
+
+/// Children shown on one page of a menu, from `MENU_PAGE_SIZE`
+pub fn menu_page_size() -> usize {
+    env::var("MENU_PAGE_SIZE")
+        .ok()
+        .and_then(|size| size.trim().parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_MENU_PAGE_SIZE)
+}
+
+/// Menu page actually shown and the number of pages, for `count` children. A page past the
+/// end, from a button sent before children were removed, shows the last one
+fn menu_pages(count: usize, size: usize, page: usize) -> (usize, usize) {
+    let pages = count.div_ceil(size).max(1);
+    (page.min(pages - 1), pages)
+}
+
+/// "◀ 1/3 ▶" row of a paginated menu, the arrows wrapping around at either end
+fn pagination_row(category: &str, page: usize, pages: usize) -> Vec<InlineKeyboardButton> {
+    let paginate = |page: usize| CallbackAction::Paginate {
+        route: category.to_string(),
+        page,
+    };
+    vec![
+        paginate((page + pages - 1) % pages).button("◀"),
+        paginate(page).button(format!("{}/{}", page + 1, pages)),
+        paginate((page + 1) % pages).button("▶"),
+    ]
+}
+
+/// Page opened by a typed keyword, if it is available now
+pub fn find_by_keyword(text: &str) -> Option<String> {
+    let text = text.trim().to_lowercase();
+    let now = Utc::now();
+    contents::current()
+        .pages
+        .iter()
+        .find(|(_, page)| page.is_available(now) && page.keywords.contains(&text))
+        .map(|(route, _)| route.clone())
 }
 
 pub fn build_buttons(category: &str, is_external: bool) -> InlineKeyboardMarkup {
@@ -29,10 +146,25 @@ pub fn build_buttons_with_user(
     category: &str,
     is_external: bool,
     user_id: Option<i64>,
+) -> InlineKeyboardMarkup {
+    build_buttons_in(&contents::current(), category, is_external, user_id, 0)
+}
+
+/// Buttons of a route within a given content set, such as a preview of an edit. Menus with
+/// more children than `menu_page_size` show the given page of them
+pub fn build_buttons_in(
+    content: &ContentSet,
+    category: &str,
+    is_external: bool,
+    user_id: Option<i64>,
+    menu_page: usize,
 ) -> InlineKeyboardMarkup {
     let mut buttons: Vec<Vec<InlineKeyboardButton>> = vec![];
 
-    let route = ROUTES.get(category).expect("Route not found");
+    let Some(route) = content.routes.get(category) else {
+        error!("Route {} not found", category);
+        return InlineKeyboardMarkup::new(buttons);
+    };
 
     // Handle subscription pages with dynamic subscribe/unsubscribe buttons
     if category.starts_with("subscriptions_") {
@@ -50,25 +182,22 @@ pub fn build_buttons_with_user(
 
             if is_subscribed {
                 // Show unsubscribe button
-                let unsubscribe_path = format!("/unsubscribe_{}", subscription_type);
-                buttons.push(vec![InlineKeyboardButton::callback(
-                    "❌ Отписаться",
-                    &unsubscribe_path,
-                )]);
+                buttons.push(vec![CallbackAction::Unsubscribe(
+                    subscription_type.to_string(),
+                )
+                .button("❌ Отписаться")]);
             } else {
                 // Show subscribe button
-                let subscribe_path = format!("/subscribe_{}", subscription_type);
-                buttons.push(vec![InlineKeyboardButton::callback(
-                    "✅ Подписаться",
-                    &subscribe_path,
-                )]);
+                buttons.push(vec![CallbackAction::Subscribe(
+                    subscription_type.to_string(),
+                )
+                .button("✅ Подписаться")]);
             }
         }
 
-        // buttons.push(vec![InlineKeyboardButton::callback(
-        //     "⬅️ Назад к подпискам",
-        //     "/subscriptions",
-        // )]);
+        if let Some(back) = back_button(content, route) {
+            buttons.push(vec![back]);
+        }
 
         return InlineKeyboardMarkup::new(buttons);
     }
@@ -77,9 +206,29 @@ pub fn build_buttons_with_user(
         let mut chunked: Vec<Vec<InlineKeyboardButton>> = Vec::new();
         let mut current_row: Vec<InlineKeyboardButton> = Vec::new();
 
-        for child in children {
-            let child_route = ROUTES.get(child).expect("Route not found");
-            let button = InlineKeyboardButton::callback(&child_route.label, &child_route.path);
+        let now = Utc::now();
+        let available: Vec<(&String, &Route)> = children
+            .iter()
+            .filter_map(|child| {
+                let child_route = content.routes.get(child);
+                if child_route.is_none() {
+                    error!("Child {} of route {} not found", child, category);
+                }
+                Some((child, child_route?))
+            })
+            // The validity window of a child is read from its page
+            .filter(|(child, _)| {
+                content
+                    .pages
+                    .get(child.as_str())
+                    .is_some_and(|page| page.is_available(now))
+            })
+            .collect();
+        let size = menu_page_size();
+        let (menu_page, pages) = menu_pages(available.len(), size, menu_page);
+
+        for (child, child_route) in available.into_iter().skip(menu_page * size).take(size) {
+            let button = CallbackAction::Navigate(child.clone()).button(&child_route.label);
 
             if child_route.label.chars().count() > 20 {
                 // Flush any accumulated small buttons before adding a large button row
@@ -101,15 +250,90 @@ pub fn build_buttons_with_user(
         }
 
         buttons.append(&mut chunked);
+        if pages > 1 {
+            buttons.push(pagination_row(category, menu_page, pages));
+        }
+        if let Some(back) = back_button(content, route) {
+            buttons.push(vec![back]);
+        }
     } else {
         if is_external {
             if let Some(external) = &route.external {
-                let url = Url::parse(&external[1]).unwrap();
-                buttons.push(vec![InlineKeyboardButton::url(&external[0], url)]);
+                match Url::parse(&external[1]) {
+                    Ok(url) => buttons.push(vec![InlineKeyboardButton::url(&external[0], url)]),
+                    Err(e) => error!("Invalid external URL of route {}: {}", category, e),
+                }
             }
         } else {
-            buttons.push(vec![InlineKeyboardButton::callback("На Главную", "start")]);
+            let mut row: Vec<InlineKeyboardButton> =
+                back_button(content, route).into_iter().collect();
+            row.push(CallbackAction::Navigate(ROOT.to_string()).button("На Главную"));
+            buttons.push(row);
         }
     }
     InlineKeyboardMarkup::new(buttons)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::InlineKeyboardButtonKind;
+
+    #[test]
+    fn test_breadcrumb() {
+        let content = ContentSet::new(contents::embedded_sources().unwrap()).unwrap();
+        assert_eq!(
+            breadcrumb(&content, "plastic"),
+            vec!["Главная", "♻️ Переработка", "Пластик"]
+        );
+        assert_eq!(
+            breadcrumb_line(&content, "plastic").as_deref(),
+            Some("Главная › ♻️ Переработка › Пластик")
+        );
+        assert_eq!(breadcrumb_line(&content, "recycling"), None);
+        assert_eq!(
+            content.routes["plastic"].parent.as_deref(),
+            Some("recycling")
+        );
+    }
+
+    #[test]
+    fn test_menu_pages() {
+        assert_eq!(menu_pages(0, 8, 0), (0, 1));
+        assert_eq!(menu_pages(8, 8, 0), (0, 1));
+        assert_eq!(menu_pages(17, 8, 1), (1, 3));
+        assert_eq!(menu_pages(17, 8, 5), (2, 3));
+
+        let row = pagination_row("recycling", 0, 3);
+        let data: Vec<_> = row
+            .iter()
+            .map(|button| match &button.kind {
+                InlineKeyboardButtonKind::CallbackData(data) => CallbackAction::decode(data),
+                _ => None,
+            })
+            .collect();
+        let paginate = |page| {
+            Some(CallbackAction::Paginate {
+                route: "recycling".into(),
+                page,
+            })
+        };
+        assert_eq!(data, vec![paginate(2), paginate(0), paginate(1)]);
+        assert_eq!(row[1].text, "1/3");
+    }
+
+    #[test]
+    fn test_routes_from_pages() {
+        let pages = contents::load_embedded().unwrap();
+        assert_eq!(
+            routes_from_pages(&pages)["start"].children.as_deref(),
+            Some(
+                &[
+                    "recycling".to_string(),
+                    "give_away".to_string(),
+                    "faq".to_string()
+                ][..]
+            )
+        );
+    }
+}
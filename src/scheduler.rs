@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use teloxide::{prelude::Requester, types::ChatId, Bot};
+
+use crate::audience::Audience;
+use crate::broadcaster;
+use crate::campaign::CAMPAIGNS;
+use crate::commands::{CampaignCommand, ADMIN_ID};
+use crate::db::{self, JobStatus};
+
+/// How often the scheduler looks for due jobs
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Start the background task that hands scheduled broadcasts over to the worker when they are due
+/// and delivers campaign days at their publish time
+pub fn spawn_scheduler(bot: Bot) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = start_due_jobs(&bot).await {
+                error!("Failed to start scheduled broadcasts: {:?}", e);
+            }
+            if let Err(e) = deliver_due_campaign_days(&bot).await {
+                error!("Failed to deliver campaign days: {:?}", e);
+            }
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    });
+}
+
+async fn start_due_jobs(bot: &Bot) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let due = db::get_scheduled_broadcast_jobs()
+        .await?
+        .into_iter()
+        .take_while(|job| job.scheduled_at.is_none_or(|at| at <= now));
+
+    for job in due {
+        let admin_chat_id = ChatId(job.admin_chat_id);
+        let audience = match job.audience.parse::<Audience>() {
+            Ok(audience) => audience,
+            Err(err) => {
+                error!(
+                    "Scheduled broadcast {} has invalid audience: {}",
+                    job.id, err
+                );
+                db::update_broadcast_job_status(&job, JobStatus::Cancelled).await?;
+                bot.send_message(
+                    admin_chat_id,
+                    format!("Запланированная рассылка {} отменена: {}", job.id, err),
+                )
+                .await?;
+                continue;
+            }
+        };
+
+        let recipients = audience.resolve().await?;
+        info!(
+            "Starting scheduled broadcast {} ({} recipients)",
+            job.id,
+            recipients.len()
+        );
+        let count = recipients.len();
+        if !db::start_scheduled_broadcast_job(&job, recipients).await? {
+            info!(
+                "Scheduled broadcast {} was cancelled before it started",
+                job.id
+            );
+            continue;
+        }
+        broadcaster::notify_new_job();
+
+        bot.send_message(
+            admin_chat_id,
+            format!(
+                "Запланированная рассылка {} ({}) запущена: {} получателей.",
+                job.record_id(),
+                job.route,
+                count
+            ),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn deliver_due_campaign_days(bot: &Bot) -> anyhow::Result<()> {
+    for campaign in CAMPAIGNS.iter() {
+        let delivered = db::get_delivered_campaign_days(&campaign.key, &campaign.season).await?;
+        for day in campaign.due(Utc::now(), &delivered) {
+            // Already queued, or cancelled by the admin
+            if !db::get_campaign_broadcast_jobs(&campaign.key, &campaign.season, day.day)
+                .await?
+                .is_empty()
+            {
+                continue;
+            }
+            info!("Delivering {} day {}", campaign.key, day.day);
+            if let Err(e) =
+                CampaignCommand::deliver_day(bot, ChatId(ADMIN_ID), campaign, day, false).await
+            {
+                error!(
+                    "Failed to deliver {} day {}: {:?}",
+                    campaign.key, day.day, e
+                );
+            }
+        }
+    }
+    Ok(())
+}
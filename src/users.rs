@@ -1,9 +1,171 @@
-use crate::db;
+use crate::db::{self, UserProfile};
 use anyhow::Result;
+use log::error;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-/// Store a user ID in the database
+/// Minimum interval between two activity writes for the same user
+pub const ACTIVITY_DEBOUNCE: Duration = Duration::from_secs(60);
+
+static ACTIVITY: Lazy<Mutex<ActivityBuffer>> = Lazy::new(|| Mutex::new(ActivityBuffer::default()));
+
+/// Kind of update a user sent to the bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Message,
+    Callback,
+}
+
+/// Counters and profile ready to be written to the database
+#[derive(Debug, PartialEq)]
+pub struct ActivityFlush {
+    pub profile: UserProfile,
+    pub messages: i64,
+    pub callbacks: i64,
+}
+
+#[derive(Debug, Default)]
+struct PendingActivity {
+    last_flush: Option<Instant>,
+    profile: UserProfile,
+    messages: i64,
+    callbacks: i64,
+}
+
+/// In-memory activity counters, flushed at most once per `ACTIVITY_DEBOUNCE` per user
+/// unless the profile changed in between
+#[derive(Debug, Default)]
+pub struct ActivityBuffer {
+    pending: HashMap<i64, PendingActivity>,
+}
+
+impl ActivityBuffer {
+    /// Record an interaction, returning what should be written if the debounce interval passed
+    pub fn record(
+        &mut self,
+        user_id: i64,
+        activity: Activity,
+        profile: UserProfile,
+        now: Instant,
+    ) -> Option<ActivityFlush> {
+        let entry = self.pending.entry(user_id).or_default();
+        match activity {
+            Activity::Message => entry.messages += 1,
+            Activity::Callback => entry.callbacks += 1,
+        }
+
+        let profile_changed = entry.profile != profile;
+        entry.profile = profile;
+
+        let due = match entry.last_flush {
+            Some(last_flush) => now.duration_since(last_flush) >= ACTIVITY_DEBOUNCE,
+            None => true,
+        };
+        if !due && !profile_changed {
+            return None;
+        }
+
+        entry.last_flush = Some(now);
+        Some(entry.take_flush())
+    }
+
+    /// Forget the users not seen for `ACTIVITY_DEBOUNCE`, their next interaction is written
+    /// right away anyway. Returns the counters they still had pending
+    pub fn prune(&mut self, now: Instant) -> Vec<(i64, ActivityFlush)> {
+        let stale: Vec<i64> = self
+            .pending
+            .iter()
+            .filter(|(_, entry)| {
+                entry
+                    .last_flush
+                    .is_none_or(|last_flush| now.duration_since(last_flush) >= ACTIVITY_DEBOUNCE)
+            })
+            .map(|(user_id, _)| *user_id)
+            .collect();
+
+        stale
+            .into_iter()
+            .filter_map(|user_id| {
+                let mut entry = self.pending.remove(&user_id)?;
+                (entry.messages > 0 || entry.callbacks > 0).then(|| (user_id, entry.take_flush()))
+            })
+            .collect()
+    }
+
+    /// Put back counters whose write failed, they go out with the user's next flush
+    pub fn restore(&mut self, user_id: i64, flush: ActivityFlush) {
+        let entry = self
+            .pending
+            .entry(user_id)
+            .or_insert_with(|| PendingActivity {
+                profile: flush.profile,
+                ..Default::default()
+            });
+        entry.messages += flush.messages;
+        entry.callbacks += flush.callbacks;
+    }
+}
+
+impl PendingActivity {
+    fn take_flush(&mut self) -> ActivityFlush {
+        ActivityFlush {
+            profile: self.profile.clone(),
+            messages: std::mem::take(&mut self.messages),
+            callbacks: std::mem::take(&mut self.callbacks),
+        }
+    }
+}
+
+/// Store a user ID in the database, clearing the blacklist flag of a returning user
 pub async fn store_user(user_id: i64) -> Result<bool> {
-    db::store_user(user_id).await
+    let is_new_user = db::store_user(user_id).await?;
+    if !is_new_user {
+        db::unblacklist_user(user_id).await?;
+    }
+    Ok(is_new_user)
+}
+
+/// Record an interaction of a Telegram user, updating the profile and counters (debounced)
+pub async fn track_activity(user: &teloxide::types::User, activity: Activity) -> Result<()> {
+    let user_id: i64 = user.id.0.try_into()?;
+    let profile = UserProfile {
+        first_name: user.first_name.clone(),
+        username: user.username.clone(),
+        language_code: user.language_code.clone(),
+        is_premium: user.is_premium,
+    };
+
+    let now = Instant::now();
+    let flushes = {
+        let mut buffer = ACTIVITY.lock().unwrap();
+        match buffer.record(user_id, activity, profile, now) {
+            // Pruned along with the writes, so the buffer only holds recently active users
+            Some(flush) => {
+                let mut flushes = buffer.prune(now);
+                flushes.push((user_id, flush));
+                flushes
+            }
+            None => vec![],
+        }
+    };
+
+    // A failed write must not lose the counters of this user or of the ones after it
+    for (user_id, flush) in flushes {
+        let result = db::update_user_activity(
+            user_id,
+            flush.profile.clone(),
+            flush.messages,
+            flush.callbacks,
+        )
+        .await;
+        if let Err(e) = result {
+            error!("Failed to store activity of user {}: {:?}", user_id, e);
+            ACTIVITY.lock().unwrap().restore(user_id, flush);
+        }
+    }
+    Ok(())
 }
 
 /// Get all stored user IDs
@@ -20,3 +182,168 @@ pub async fn get_active_users() -> Result<Vec<i64>> {
 pub async fn blacklist_user(user_id: i64) -> Result<bool> {
     db::blacklist_user(user_id).await
 }
+
+/// Remember the last location a user sent, used for audience targeting
+pub async fn set_home_location(user_id: i64, latitude: f64, longitude: f64) -> Result<()> {
+    db::set_user_home_location(user_id, latitude, longitude).await
+}
+
+/// Remove a user from the blacklist (the user interacted with the bot again)
+pub async fn unblacklist_user(user_id: i64) -> Result<bool> {
+    db::unblacklist_user(user_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(first_name: &str) -> UserProfile {
+        UserProfile {
+            first_name: first_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_activity_debounce() {
+        let mut buffer = ActivityBuffer::default();
+        let start = Instant::now();
+
+        let first = buffer.record(1, Activity::Message, profile("Anna"), start);
+        assert_eq!(
+            first,
+            Some(ActivityFlush {
+                profile: profile("Anna"),
+                messages: 1,
+                callbacks: 0,
+            })
+        );
+
+        assert!(buffer
+            .record(
+                1,
+                Activity::Callback,
+                profile("Anna"),
+                start + Duration::from_secs(1)
+            )
+            .is_none());
+        assert!(buffer
+            .record(
+                1,
+                Activity::Message,
+                profile("Anna"),
+                start + Duration::from_secs(2)
+            )
+            .is_none());
+
+        let flushed = buffer
+            .record(
+                1,
+                Activity::Callback,
+                profile("Anna"),
+                start + ACTIVITY_DEBOUNCE,
+            )
+            .unwrap();
+        assert_eq!(flushed.messages, 1);
+        assert_eq!(flushed.callbacks, 2);
+    }
+
+    #[test]
+    fn test_activity_prune() {
+        let mut buffer = ActivityBuffer::default();
+        let start = Instant::now();
+
+        buffer.record(1, Activity::Message, profile("Anna"), start);
+        buffer.record(2, Activity::Message, profile("Boris"), start);
+        buffer.record(
+            2,
+            Activity::Callback,
+            profile("Boris"),
+            start + Duration::from_secs(1),
+        );
+        buffer.record(
+            3,
+            Activity::Message,
+            profile("Vera"),
+            start + Duration::from_secs(30),
+        );
+
+        let flushes = buffer.prune(start + ACTIVITY_DEBOUNCE);
+        assert_eq!(
+            flushes,
+            vec![(
+                2,
+                ActivityFlush {
+                    profile: profile("Boris"),
+                    messages: 0,
+                    callbacks: 1,
+                }
+            )]
+        );
+        assert_eq!(buffer.pending.len(), 1);
+
+        // A pruned user is written again on the next interaction
+        assert!(buffer
+            .record(
+                1,
+                Activity::Message,
+                profile("Anna"),
+                start + ACTIVITY_DEBOUNCE
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn test_activity_flushes_on_profile_change() {
+        let mut buffer = ActivityBuffer::default();
+        let start = Instant::now();
+
+        buffer.record(1, Activity::Message, profile("Anna"), start);
+        let flushed = buffer
+            .record(
+                1,
+                Activity::Message,
+                profile("Anya"),
+                start + Duration::from_secs(1),
+            )
+            .unwrap();
+        assert_eq!(flushed.profile, profile("Anya"));
+        assert_eq!(flushed.messages, 1);
+    }
+
+    #[test]
+    fn test_activity_restore() {
+        let mut buffer = ActivityBuffer::default();
+        let start = Instant::now();
+
+        // The write of the first flush failed, its counters join the next one
+        let failed = buffer
+            .record(1, Activity::Message, profile("Anna"), start)
+            .unwrap();
+        buffer.restore(1, failed);
+        let flushed = buffer
+            .record(
+                1,
+                Activity::Callback,
+                profile("Anna"),
+                start + ACTIVITY_DEBOUNCE,
+            )
+            .unwrap();
+        assert_eq!(flushed.messages, 1);
+        assert_eq!(flushed.callbacks, 1);
+
+        // A pruned user whose write failed is flushed by the next prune
+        buffer.restore(
+            2,
+            ActivityFlush {
+                profile: profile("Boris"),
+                messages: 2,
+                callbacks: 0,
+            },
+        );
+        let flushes = buffer.prune(start + ACTIVITY_DEBOUNCE);
+        assert_eq!(flushes.len(), 1);
+        assert_eq!(flushes[0].0, 2);
+        assert_eq!(flushes[0].1.messages, 2);
+    }
+}
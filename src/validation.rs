@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet};
+
+use reqwest::Url;
+
+use crate::callback::{CallbackAction, MAX_DATA_LEN};
+use crate::page::{Page, PageFormat};
+
+/// Tags Telegram accepts in HTML messages
+const TELEGRAM_TAGS: &[&str] = &[
+    "b",
+    "strong",
+    "i",
+    "em",
+    "u",
+    "ins",
+    "s",
+    "strike",
+    "del",
+    "span",
+    "tg-spoiler",
+    "a",
+    "tg-emoji",
+    "code",
+    "pre",
+    "blockquote",
+];
+/// Named entities Telegram decodes in HTML messages, numeric ones are accepted as well
+const TELEGRAM_ENTITIES: &[&str] = &["lt", "gt", "amp", "quot"];
+/// Longest entity looked for after an `&`, semicolon included
+const ENTITY_LIMIT: usize = 10;
+/// Telegram's limit for the text of a message, in characters
+const MESSAGE_LIMIT: usize = 4096;
+/// Longest button label that still shows in full on a phone
+const LABEL_LIMIT: usize = 64;
+/// Highest page index the callback data of a paginated menu is checked with
+const MAX_MENU_PAGE: usize = 999;
+
+/// Inconsistencies in the front-matter and markup of the pages, in route order
+pub fn validate_pages(pages: &HashMap<String, Page>) -> Vec<String> {
+    let mut problems = vec![];
+    let mut keywords: HashMap<&str, &str> = HashMap::new();
+
+    let parents: HashSet<&str> = pages
+        .values()
+        .filter_map(|page| page.parent.as_deref())
+        .collect();
+
+    let mut routes: Vec<&String> = pages.keys().collect();
+    routes.sort();
+    for route in routes {
+        let page = &pages[route];
+        if let Some(parent) = &page.parent {
+            if !pages.contains_key(parent) {
+                problems.push(format!("{}: parent {} does not exist", route, parent));
+            }
+            match page.label.as_ref().or(page.title.as_ref()) {
+                None => problems.push(format!("{}: has a parent but no label", route)),
+                Some(label) if label.chars().count() > LABEL_LIMIT => problems.push(format!(
+                    "{}: label is longer than {} characters",
+                    route, LABEL_LIMIT
+                )),
+                Some(_) => {}
+            }
+            if !CallbackAction::Navigate(route.clone()).fits() {
+                problems.push(format!(
+                    "{}: route is too long for a button, {} bytes of callback data at most",
+                    route, MAX_DATA_LEN
+                ));
+            }
+        }
+
+        // A menu with many children carries the page index in its buttons too
+        let paginate = CallbackAction::Paginate {
+            route: route.clone(),
+            page: MAX_MENU_PAGE,
+        };
+        if parents.contains(route.as_str()) && !paginate.fits() {
+            problems.push(format!(
+                "{}: route is too long for the page buttons of its menu, {} bytes of callback data at most",
+                route, MAX_DATA_LEN
+            ));
+        }
+
+        // Walk up the parents to catch cycles
+        let mut seen = HashSet::from([route.as_str()]);
+        let mut current = page.parent.as_deref();
+        while let Some(parent) = current {
+            if !seen.insert(parent) {
+                problems.push(format!("{}: parent cycle through {}", route, parent));
+                break;
+            }
+            current = pages.get(parent).and_then(|page| page.parent.as_deref());
+        }
+
+        if let (Some(from), Some(until)) = (page.valid_from, page.valid_until) {
+            if from >= until {
+                problems.push(format!("{}: valid_from is not before valid_until", route));
+            }
+        }
+        for file in page.missing_media() {
+            problems.push(format!("{}: media {} not found", route, file));
+        }
+        if let Some((label, url)) = &page.external {
+            if Url::parse(url).is_err() {
+                problems.push(format!("{}: invalid external URL {}", route, url));
+            }
+            if label.chars().count() > LABEL_LIMIT {
+                problems.push(format!(
+                    "{}: external link label is longer than {} characters",
+                    route, LABEL_LIMIT
+                ));
+            }
+        }
+        for keyword in &page.keywords {
+            if let Some(other) = keywords.insert(keyword, route) {
+                problems.push(format!(
+                    "{}: keyword {} is already used by {}",
+                    route, keyword, other
+                ));
+            }
+        }
+
+        let length = match page.parse_mode {
+            PageFormat::Html => match validate_html(&page.body) {
+                Ok(length) => length,
+                Err(e) => {
+                    problems.push(format!("{}: {}", route, e));
+                    continue;
+                }
+            },
+            PageFormat::Markdown | PageFormat::Plain => page.body.chars().count(),
+        };
+        if length > MESSAGE_LIMIT {
+            problems.push(format!(
+                "{}: text is longer than {} characters",
+                route, MESSAGE_LIMIT
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Check that the text only uses tags and entities Telegram supports, with every tag closed
+/// in order. Returns the length of the text as Telegram counts it, without the markup
+pub fn validate_html(text: &str) -> Result<usize, String> {
+    let mut open: Vec<String> = vec![];
+    let mut length = 0;
+    let mut rest = text;
+
+    while let Some(index) = rest.find(['<', '&']) {
+        length += rest[..index].chars().count();
+        let tail = &rest[index..];
+
+        if let Some(entity) = tail.strip_prefix('&') {
+            let name = entity
+                .find(';')
+                .filter(|end| *end < ENTITY_LIMIT)
+                .map(|end| &entity[..end])
+                .filter(|name| is_entity(name))
+                .ok_or_else(|| {
+                    let shown: String = tail.chars().take(ENTITY_LIMIT).collect();
+                    format!("unescaped & in \"{}\", write &amp;", shown)
+                })?;
+            length += 1;
+            rest = &entity[name.len() + 1..];
+            continue;
+        }
+
+        let end = tail
+            .find('>')
+            .ok_or_else(|| "unescaped < without a closing >, write &lt;".to_string())?;
+        let tag = &tail[1..end];
+        rest = &tail[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim().to_lowercase();
+            match open.pop() {
+                Some(opened) if opened == name => {}
+                Some(opened) => return Err(format!("</{}> closes <{}>", name, opened)),
+                None => return Err(format!("</{}> has no opening tag", name)),
+            }
+        } else {
+            let name = tag
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            if !TELEGRAM_TAGS.contains(&name.as_str()) {
+                return Err(format!("unsupported tag <{}>", name));
+            }
+            open.push(name);
+        }
+    }
+    length += rest.chars().count();
+
+    match open.pop() {
+        Some(name) => Err(format!("<{}> is not closed", name)),
+        None => Ok(length),
+    }
+}
+
+/// Whether the text between `&` and `;` is an entity Telegram decodes
+fn is_entity(name: &str) -> bool {
+    if TELEGRAM_ENTITIES.contains(&name) {
+        return true;
+    }
+    match name.strip_prefix('#') {
+        Some(code) => match code.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).is_ok(),
+            None => code.parse::<u32>().is_ok(),
+        },
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contents;
+
+    #[test]
+    fn test_contents_are_valid() {
+        let pages = contents::load_embedded().unwrap();
+        assert_eq!(validate_pages(&pages), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_validate_pages() {
+        let long_label = "Очень длинная подпись ".repeat(4);
+        let e = format!(
+            "---\nparent: d\nlabel: {}\n---\nТекст <b>жирный",
+            long_label
+        );
+        let pages: HashMap<String, Page> = [
+            ("a", "---\nparent: b\nlabel: A\nkeywords: [eco]\n---\n"),
+            ("b", "---\nparent: a\nlabel: B\nkeywords: [eco]\n---\n"),
+            ("c", "---\nparent: missing\nmedia: missing.jpg\n---\n"),
+            (
+                "d",
+                "---\nvalid_from: 2025-12-31\nvalid_until: 2025-12-01\n---\n",
+            ),
+            ("e", e.as_str()),
+        ]
+        .into_iter()
+        .map(|(route, text)| (route.to_string(), Page::parse(text).unwrap()))
+        .collect();
+
+        assert_eq!(
+            validate_pages(&pages),
+            vec![
+                "a: parent cycle through a",
+                "b: parent cycle through b",
+                "b: keyword eco is already used by a",
+                "c: parent missing does not exist",
+                "c: has a parent but no label",
+                "c: media missing.jpg not found",
+                "d: valid_from is not before valid_until",
+                "e: label is longer than 64 characters",
+                "e: <b> is not closed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_html() {
+        assert_eq!(
+            validate_html("<b>Пластик</b> &amp; <a href=\"https://ecoklgd.ru\">сайт</a> &#8470;"),
+            Ok(16)
+        );
+        assert_eq!(
+            validate_html("<br>"),
+            Err("unsupported tag <br>".to_string())
+        );
+        assert_eq!(
+            validate_html("<b><i>x</b></i>"),
+            Err("</b> closes <i>".to_string())
+        );
+        assert_eq!(
+            validate_html("x</b>"),
+            Err("</b> has no opening tag".to_string())
+        );
+        assert_eq!(
+            validate_html("1 < 2"),
+            Err("unescaped < without a closing >, write &lt;".to_string())
+        );
+        assert_eq!(
+            validate_html("Tom & Jerry"),
+            Err("unescaped & in \"& Jerry\", write &amp;".to_string())
+        );
+    }
+}